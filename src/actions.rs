@@ -1,18 +1,190 @@
-use gesture_detector::{Direction, Side, GestureDetector};
+//! Actions run for gestures, and the grammar they are given in on the command
+//! line, e.g. "from left to right with two fingers run 'xeyes'".
+
+use gesture_detector::{Direction, Side, Gesture, GestureDetector};
 
 use std::str::{from_utf8, FromStr};
 use nom::{space, digit};
 use nom::IResult::Done;
 use std::process::Command;
-use std::ffi::OsStr;
-use std::os::unix::ffi::OsStrExt;
 use devicegrab::*;
+use xconn::XConnection;
+use shell_words;
+use supervisor;
+use supervisor::ChildHandle;
+use std::cell::RefCell;
+use fake_input;
+use ewmh;
+use ewmh::WindowInfo;
+use layers;
+use config::Config;
+
+/// What an action does, called with the gesture it is run for.
+pub type ActionFunction = Box<dyn Fn(&mut GestureDetector, &Gesture) -> ()>;
+
+/// Limits an action to windows with e.g. "in window class 'Firefox'".
+pub enum WindowMatch {
+    /// WM_CLASS class and instance names are compared case-insensitively.
+    Class(String),
+    /// The WM_CLASS instance name.
+    Instance(String),
+    /// Matches if the title contains the string.
+    Title(String),
+}
+
+impl WindowMatch {
+    /// Returns whether the window is one the action is for.
+    pub fn matches(&self, window: &WindowInfo) -> bool {
+        match *self {
+            WindowMatch::Class(ref class) => window.class.to_lowercase() == class.to_lowercase(),
+            WindowMatch::Instance(ref instance) => window.instance.to_lowercase() == instance.to_lowercase(),
+            WindowMatch::Title(ref title) => window.title.contains(title.as_str()),
+        }
+    }
+}
 
+/// An action bound to a gesture.
 pub struct Action {
+    /// The edge the gesture starts at.
     pub side: Side,
+    /// The number of fingers of the gesture.
     pub num_fingers: u32,
+    /// The direction of the gesture.
     pub direction: Direction,
-    pub function: Box<Fn(&mut GestureDetector) -> ()>
+    /// What the action does.
+    pub function: ActionFunction,
+    /// If set, the action is only run when the active window matches.
+    pub window: Option<WindowMatch>,
+    /// The layer the action belongs to, layers::DEFAULT_LAYER unless given
+    /// with "in layer".
+    pub layer: String,
+    /// The action as given on the command line.
+    pub description: String,
+    /// For triggering the action through the control socket.
+    pub name: Option<String>,
+}
+
+// Optional clauses after the gesture and the command.
+enum ActionModifier {
+    Window(WindowMatch),
+    Layer(String),
+    Name(String),
+}
+
+fn build_action(side: Side, direction: Direction, num_fingers: u32, function: ActionFunction, modifiers: Vec<ActionModifier>) -> Action {
+    let mut action = Action {
+        side: side,
+        direction: direction,
+        num_fingers: num_fingers,
+        function: function,
+        window: None,
+        layer: String::from(layers::DEFAULT_LAYER),
+        description: String::new(),
+        name: None,
+    };
+
+    for modifier in modifiers {
+        match modifier {
+            ActionModifier::Window(window_match) => action.window = Some(window_match),
+            ActionModifier::Layer(layer) => action.layer = layer,
+            ActionModifier::Name(name) => action.name = Some(name),
+        }
+    }
+
+    action
+}
+
+/// Returns the actions to run for the gesture in the layer. Actions specific
+/// to the active window take precedence over global actions for the same
+/// gesture.
+pub fn find_actions<'a>(actions: &'a [Action], layer: &str, gesture: &Gesture, window: Option<&WindowInfo>) -> Vec<&'a Action> {
+    let gesture_actions = actions.iter().filter(|action| {
+        action.layer == layer &&
+        action.side == gesture.side &&
+        action.direction == gesture.direction &&
+        action.num_fingers == gesture.num_fingers
+    });
+
+    let window_actions: Vec<&Action> = gesture_actions.clone()
+        .filter(|action| match (action.window.as_ref(), window) {
+            (Some(window_match), Some(window)) => window_match.matches(window),
+            _ => false,
+        })
+        .collect();
+
+    if !window_actions.is_empty() {
+        return window_actions;
+    }
+
+    gesture_actions.filter(|action| action.window.is_none()).collect()
+}
+
+// Describes the gesture that triggered a command. Each variable is passed to
+// the command as EDGY_<NAME> in the environment and can be used as a {name}
+// placeholder in the command string.
+fn gesture_variables(gesture_detector: &GestureDetector, gesture: &Gesture) -> Vec<(&'static str, String)> {
+    let device_name = gesture_detector.config.device_names.borrow()
+        .get(&gesture.device_id)
+        .cloned()
+        .unwrap_or_default();
+
+    vec![("side", gesture.side.to_string()),
+         ("direction", gesture.direction.to_string()),
+         ("fingers", gesture.num_fingers.to_string()),
+         ("start_x", gesture.start_x.round().to_string()),
+         ("start_y", gesture.start_y.round().to_string()),
+         ("end_x", gesture.end_x.round().to_string()),
+         ("end_y", gesture.end_y.round().to_string()),
+         ("output", gesture_detector.config.x11.as_ref()
+                        .and_then(|x11| x11.get_output_name_at(gesture.start_x, gesture.start_y))
+                        .unwrap_or_default()),
+         ("device", device_name)]
+}
+
+// Runs f with the X connection. Actions using X do nothing without one.
+fn with_x11<F>(gesture_detector: &GestureDetector, f: F) where F: FnOnce(&XConnection) {
+    match gesture_detector.config.x11 {
+        Some(ref x11) => f(x11),
+        None => println!("The action needs an X display."),
+    }
+}
+
+// Replaces {name} placeholders with the values of the variables. Unknown
+// placeholders are left as they are, and {{ is a literal {.
+fn expand_placeholders(template: &str, variables: &[(&'static str, String)]) -> String {
+    let mut result = String::new();
+    let mut rest = template;
+
+    while let Some(open) = rest.find('{') {
+        result.push_str(&rest[..open]);
+        rest = &rest[open..];
+
+        if rest.starts_with("{{") {
+            result.push('{');
+            rest = &rest[2..];
+            continue;
+        }
+
+        let value = rest.find('}').and_then(|close| {
+            variables.iter()
+                .find(|&&(name, _)| name == &rest[1..close])
+                .map(|variable| (close, variable.1.as_str()))
+        });
+
+        match value {
+            Some((close, value)) => {
+                result.push_str(value);
+                rest = &rest[close + 1..];
+            },
+            None => {
+                result.push('{');
+                rest = &rest[1..];
+            },
+        }
+    }
+
+    result.push_str(rest);
+    result
 }
 
 named!(direction<&[u8], Direction>, alt!(
@@ -110,124 +282,730 @@ named!(from_to_fingers<&[u8], (Side, Direction, u32)>,
           )
       ));
 
-named!(run_command<&[u8], Box<Fn(&mut GestureDetector) -> ()> >,
+// Removes the backslashes escaping the quote character that delimited a quoted
+// string. Other escapes are kept, e.g. for a shell to handle.
+fn unescape_quote(raw: &[u8], quote: u8) -> String {
+    let mut result = Vec::new();
+    let mut bytes = raw.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte != b'\\' {
+            result.push(byte);
+            continue;
+        }
+        match bytes.next() {
+            Some(&escaped) if escaped == quote => result.push(quote),
+            Some(&escaped) => {
+                result.push(b'\\');
+                result.push(escaped);
+            },
+            None => result.push(b'\\'),
+        }
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+// A string in single or double quotes, in which the quote character can be
+// escaped with a backslash. The escapes of the quote character are removed, so
+// that e.g. a shell command is seen as it would be typed.
+named!(quoted_string<&[u8], String>,
+    alt!(
+        map!(
+            delimited!(
+                char!('"'),
+                escaped!(is_not!("\\\""), '\\', take!(1)),
+                char!('"')
+            ),
+            |raw| unescape_quote(raw, b'"')
+        )
+        |
+        map!(
+            delimited!(
+                char!('\''),
+                escaped!(is_not!("\\'"), '\\', take!(1)),
+                char!('\'')
+            ),
+            |raw| unescape_quote(raw, b'\'')
+        )
+    ));
+
+// A quoted command split into words like a shell would do.
+named!(command_words<&[u8], Vec<String> >,
+    map_res!(quoted_string, |command: String| shell_words::split(&command)));
+
+// The name of the environment variable a gesture variable is passed in.
+fn environment_name(name: &str) -> String {
+    format!("EDGY_{}", name.to_uppercase())
+}
+
+fn spawn_command(config: &Config, args: &[String], variables: &[(&'static str, String)]) -> Option<ChildHandle> {
+    let mut command = Command::new(&args[0]);
+    command.args(&args[1..]);
+    for &(name, ref value) in variables {
+        command.env(environment_name(name), value);
+    }
+    supervisor::spawn(command, args.join(" "), config.command_timeout)
+}
+
+fn run_words(gesture_detector: &mut GestureDetector, gesture: &Gesture, words: &[String]) -> Option<ChildHandle> {
+    let variables = gesture_variables(gesture_detector, gesture);
+    let args: Vec<String> = words.iter()
+        .map(|word| expand_placeholders(word, &variables))
+        .collect();
+    spawn_command(gesture_detector.config, &args, &variables)
+}
+
+// Runs the command unless the instance started by this action is still
+// running. If terminate_running is set, the running instance is terminated
+// instead.
+fn single_instance_command(words: Vec<String>, terminate_running: bool) -> ActionFunction {
+    let instance: RefCell<Option<ChildHandle>> = RefCell::new(None);
+
+    Box::new(move |gesture_detector: &mut GestureDetector, gesture: &Gesture| {
+        let mut instance = instance.borrow_mut();
+
+        if let Some(ref child) = *instance {
+            if !child.has_exited() {
+                if terminate_running {
+                    println!("Terminating {} (pid {}).", words.join(" "), child.pid);
+                    child.terminate();
+                }
+                else {
+                    println!("{} is already running (pid {}).", words.join(" "), child.pid);
+                }
+                return;
+            }
+        }
+
+        *instance = run_words(gesture_detector, gesture, &words);
+    })
+}
+
+named!(run_command<&[u8], ActionFunction>,
   chain!(
     alt!(tag!("run") | tag!("execute") | tag!("exec")) ~
     space ~
     chain!(alt!(tag!("command") | tag!("cmd")) ~ space, || {})? ~
-    cmd: alt!(
-        delimited!(
-            char!('"'),
-            is_not!("\""),
-            char!('"')
+    words: command_words,
+    || {
+        Box::new(move |gesture_detector: &mut GestureDetector, gesture: &Gesture| {
+           run_words(gesture_detector, gesture, &words);
+        })
+       }
+  ));
+
+named!(run_once_command<&[u8], ActionFunction>,
+  chain!(
+    alt!(tag!("run") | tag!("execute") | tag!("exec")) ~
+    space ~
+    tag!("once") ~
+    space ~
+    chain!(alt!(tag!("command") | tag!("cmd")) ~ space, || {})? ~
+    words: command_words,
+    || { single_instance_command(words, false) }
+  ));
+
+named!(toggle_command<&[u8], ActionFunction>,
+  chain!(
+    tag!("toggle") ~
+    space ~
+    chain!(alt!(tag!("command") | tag!("cmd")) ~ space, || {})? ~
+    words: command_words,
+    || { single_instance_command(words, true) }
+  ));
+
+// Runs the command through /bin/sh. Placeholders are not expanded, since
+// their values would not be quoted; use the environment variables instead.
+named!(shell_command<&[u8], ActionFunction>,
+  chain!(
+    tag!("shell") ~
+    space ~
+    chain!(alt!(tag!("command") | tag!("cmd")) ~ space, || {})? ~
+    cmd: quoted_string,
+    || {
+        let args = vec![String::from("/bin/sh"), String::from("-c"), cmd];
+        Box::new(move |gesture_detector: &mut GestureDetector, gesture: &Gesture| {
+           spawn_command(gesture_detector.config, &args, &gesture_variables(gesture_detector, gesture));
+        })
+       }
+  ));
+
+named!(send_keys<&[u8], ActionFunction>,
+  chain!(
+    alt!(tag!("send") | tag!("press")) ~
+    space ~
+    alt!(tag!("keys") | tag!("key")) ~
+    space ~
+    combos: map_res!(quoted_string, |keys: String| fake_input::parse_key_combos(&keys)),
+    || {
+        Box::new(move |gesture_detector: &mut GestureDetector, _: &Gesture| {
+           with_x11(gesture_detector, |x11| fake_input::send_key_combos(x11, &combos));
+        })
+       }
+  ));
+
+named!(button<&[u8], u32>,
+    alt!(
+        chain!(
+            button_val: alt!(
+                  tag!("left")   => { |_| fake_input::BUTTON_LEFT }
+                | tag!("middle") => { |_| fake_input::BUTTON_MIDDLE }
+                | tag!("right")  => { |_| fake_input::BUTTON_RIGHT }
+            ) ~
+            chain!(space ~ tag!("button"), || {})?,
+            || { button_val }
         )
-        | 
-        delimited!(
-            char!('\''),
-            is_not!("'"),
-            char!('\'')
+        | chain!(
+            tag!("button") ~
+            space ~
+            button_val: u32_str,
+            || { button_val }
         )
-    ),
+    ));
+
+// E.g. "click right button", "double click" or "click button 8".
+named!(click<&[u8], ActionFunction>,
+  chain!(
+    count: chain!(tag!("double") ~ space, || { 2 })? ~
+    tag!("click") ~
+    button_val: chain!(space ~ b: button, || { b })?,
+    || {
+        let count = count.unwrap_or(1);
+        let button_val = button_val.unwrap_or(fake_input::BUTTON_LEFT);
+        Box::new(move |gesture_detector: &mut GestureDetector, _: &Gesture| {
+           with_x11(gesture_detector, |x11| fake_input::click_button(x11, button_val, count));
+        })
+       }
+  ));
+
+// E.g. "scroll up" or "scroll down 5 times".
+named!(scroll<&[u8], ActionFunction>,
+  chain!(
+    tag!("scroll") ~
+    space ~
+    direction_val: direction ~
+    amount: chain!(
+        space ~
+        a: u32_str ~
+        chain!(space ~ alt!(tag!("times") | tag!("steps")), || {})?,
+        || { a }
+    )?,
+    || {
+        let button_val = match direction_val {
+            Direction::Up => fake_input::BUTTON_SCROLL_UP,
+            Direction::Down => fake_input::BUTTON_SCROLL_DOWN,
+            Direction::Left => fake_input::BUTTON_SCROLL_LEFT,
+            Direction::Right => fake_input::BUTTON_SCROLL_RIGHT,
+        };
+        let amount = amount.unwrap_or(1);
+        Box::new(move |gesture_detector: &mut GestureDetector, _: &Gesture| {
+           with_x11(gesture_detector, |x11| fake_input::click_button(x11, button_val, amount));
+        })
+       }
+  ));
+
+named!(window_action<&[u8], ActionFunction>,
+  chain!(
+    function_val: alt!(
+          tag!("close")    => { |_| ewmh::close_active_window as fn(&XConnection) }
+        | tag!("minimize") => { |_| ewmh::minimize_active_window as fn(&XConnection) }
+        | tag!("minimise") => { |_| ewmh::minimize_active_window as fn(&XConnection) }
+        | tag!("maximize") => { |_| ewmh::toggle_maximize_active_window as fn(&XConnection) }
+        | tag!("maximise") => { |_| ewmh::toggle_maximize_active_window as fn(&XConnection) }
+    ) ~
+    space ~
+    chain!(tag!("active") ~ space, || {})? ~
+    tag!("window"),
+    || {
+        Box::new(move |gesture_detector: &mut GestureDetector, _: &Gesture| {
+           with_x11(gesture_detector, function_val);
+        })
+       }
+  ));
+
+named!(next_previous<&[u8], i32>,
+    alt!(
+          tag!("next")     => { |_| 1 }
+        | tag!("previous") => { |_| -1 }
+        | tag!("prev")     => { |_| -1 }
+    ));
+
+// E.g. "next desktop" or "switch to previous desktop".
+named!(switch_desktop<&[u8], ActionFunction>,
+  chain!(
+    chain!(alt!(tag!("switch") | tag!("go")) ~ space ~ tag!("to") ~ space, || {})? ~
+    offset: next_previous ~
+    space ~
+    alt!(tag!("desktop") | tag!("workspace")),
+    || {
+        Box::new(move |gesture_detector: &mut GestureDetector, _: &Gesture| {
+           with_x11(gesture_detector, |x11| ewmh::switch_desktop(x11, offset));
+        })
+       }
+  ));
+
+named!(show_desktop<&[u8], ActionFunction>,
+  chain!(
+    chain!(tag!("toggle") ~ space, || {})? ~
+    tag!("show") ~
+    space ~
+    tag!("desktop"),
+    || {
+        Box::new(|gesture_detector: &mut GestureDetector, _: &Gesture| {
+           with_x11(gesture_detector, ewmh::toggle_show_desktop);
+        })
+       }
+  ));
+
+// E.g. "move window to next monitor".
+named!(move_window<&[u8], ActionFunction>,
+  chain!(
+    tag!("move") ~
+    space ~
+    chain!(tag!("active") ~ space, || {})? ~
+    tag!("window") ~
+    space ~
+    tag!("to") ~
+    space ~
+    offset: next_previous ~
+    space ~
+    alt!(tag!("monitor") | tag!("screen") | tag!("output")),
+    || {
+        Box::new(move |gesture_detector: &mut GestureDetector, _: &Gesture| {
+           with_x11(gesture_detector, |x11| ewmh::move_active_window_to_monitor(x11, offset));
+        })
+       }
+  ));
+
+named!(layer_name<&[u8], String>,
+    chain!(
+        alt!(tag!("layer") | tag!("mode")) ~
+        space ~
+        name: quoted_string,
+        || { name }
+    ));
+
+// E.g. "switch to layer 'presentation'".
+named!(switch_layer<&[u8], ActionFunction>,
+  chain!(
+    alt!(tag!("switch") | tag!("go")) ~
+    space ~
+    tag!("to") ~
+    space ~
+    layer: layer_name,
+    || {
+        Box::new(move |gesture_detector: &mut GestureDetector, _: &Gesture| {
+           gesture_detector.layers.switch(gesture_detector.config, &layer);
+        })
+       }
+  ));
+
+// E.g. "push layer 'unlock'". The layer is left with "pop layer", or when a
+// gesture not bound in it is made.
+named!(push_layer<&[u8], ActionFunction>,
+  chain!(
+    alt!(tag!("push") | tag!("enter")) ~
+    space ~
+    layer: layer_name,
     || {
-        let cmd_copy = OsStr::from_bytes(cmd).to_os_string();
-        Box::new(move |_: &mut GestureDetector| {
-           Command::new("setsid")
-                   .arg(&cmd_copy)
-                   .spawn()
-                   .expect("Failed to run command.");
+        Box::new(move |gesture_detector: &mut GestureDetector, _: &Gesture| {
+           gesture_detector.layers.push(gesture_detector.config, &layer);
         })
        }
   ));
 
-named!(touchscreen<&[u8], ()>, chain!(
+named!(pop_layer<&[u8], ActionFunction>,
+  chain!(
+    alt!(tag!("pop") | tag!("leave")) ~
+    space ~
+    alt!(tag!("layer") | tag!("mode")),
+    || {
+        Box::new(|gesture_detector: &mut GestureDetector, _: &Gesture| {
+           gesture_detector.layers.pop(gesture_detector.config);
+        })
+       }
+  ));
+
+// E.g. "touchscreen" or "touch screen 'ELAN Touchscreen'". Returns the device
+// name, if given.
+named!(touchscreen<&[u8], Option<String> >, chain!(
                          tag!("touch") ~
                          space? ~
-                         tag!("screen")?, || {}));
+                         tag!("screen")? ~
+                         device: chain!(space ~ name: quoted_string, || { name })?,
+                         || { device }));
 
-named!(disable_touchscreen<&[u8], Box<Fn(&mut GestureDetector) -> ()> >,
+named!(disable_touchscreen<&[u8], ActionFunction>,
           chain!(
             alt!(tag!("disable")
                | tag!("stop")
                | chain!(tag!("turn") ~ space ~ tag!("off"), || { &[] as &[u8] })
             ) ~
             space ~
-            touchscreen,
+            device: touchscreen,
             || { 
-                 Box::new(|gesture_detector:&mut GestureDetector| {
-                   grab_devices(&gesture_detector.config.device_ids);
+                 Box::new(move |gesture_detector:&mut GestureDetector, _: &Gesture| {
+                   let config = gesture_detector.config;
+                   disable_touch(config, &select_devices(config, device.as_deref()));
                  })
                }
           ));
 
-named!(enable_touchscreen<&[u8], Box<Fn(&mut GestureDetector) -> ()> >,
+named!(enable_touchscreen<&[u8], ActionFunction>,
           chain!(
             alt!(tag!("enable") 
                | tag!("start")
                | chain!(tag!("turn") ~ space ~ tag!("on"), || { &[] as &[u8] })) ~
             space ~
-            touchscreen,
+            device: touchscreen,
             || { 
-                 Box::new(|gesture_detector:&mut GestureDetector| {
-                   ungrab_devices(&gesture_detector.config.device_ids);
+                 Box::new(move |gesture_detector:&mut GestureDetector, _: &Gesture| {
+                   let config = gesture_detector.config;
+                   enable_touch(config, &select_devices(config, device.as_deref()));
                  })
                }
           ));
 
-named!(toggle_touchscreen<&[u8], Box<Fn(&mut GestureDetector) -> ()> >,
+named!(toggle_touchscreen<&[u8], ActionFunction>,
           chain!(
             tag!("toggle") ~
             space ~
-            touchscreen,
+            device: touchscreen,
             || { 
-                 Box::new(|gesture_detector:&mut GestureDetector| {
-                   toggle_grab_devices(&gesture_detector.config.device_ids);
+                 Box::new(move |gesture_detector:&mut GestureDetector, _: &Gesture| {
+                   let config = gesture_detector.config;
+                   toggle_touch(config, &select_devices(config, device.as_deref()));
                  })
                }
           )
       );
 
-named!(command<&[u8], Box<Fn(&mut GestureDetector) -> ()> >,
+named!(command<&[u8], ActionFunction>,
       alt!(
-            run_command
+            run_once_command
+          | run_command
+          | toggle_command
+          | shell_command
+          | send_keys
+          | click
+          | scroll
+          | window_action
+          | switch_desktop
+          | show_desktop
+          | move_window
+          | switch_layer
+          | push_layer
+          | pop_layer
           | disable_touchscreen
           | enable_touchscreen
           | toggle_touchscreen));
 
+// E.g. "in window class 'Firefox'" or "when title is 'Presentation'".
+named!(window_match<&[u8], WindowMatch>,
+    chain!(
+        alt!(tag!("in") | tag!("for") | tag!("when")) ~
+        space ~
+        chain!(tag!("window") ~ space, || {})? ~
+        kind: alt!(tag!("class") | tag!("instance") | tag!("title")) ~
+        space ~
+        chain!(tag!("is") ~ space, || {})? ~
+        value: quoted_string,
+        || {
+            match kind {
+                b"class" => WindowMatch::Class(value),
+                b"instance" => WindowMatch::Instance(value),
+                _ => WindowMatch::Title(value),
+            }
+        }
+    ));
+
+// E.g. "in layer 'presentation'".
+named!(layer_match<&[u8], String>,
+    chain!(
+        tag!("in") ~
+        space ~
+        layer: layer_name,
+        || { layer }
+    ));
+
+named!(name_match<&[u8], String>,
+    chain!(
+        tag!("named") ~
+        space ~
+        name: quoted_string,
+        || { name }
+    ));
+
+named!(action_modifiers<&[u8], Vec<ActionModifier> >,
+    many0!(
+        chain!(
+            space ~
+            modifier: alt!(
+                  layer_match  => { ActionModifier::Layer }
+                | window_match => { ActionModifier::Window }
+                | name_match   => { ActionModifier::Name }
+            ),
+            || { modifier }
+        )
+    ));
+
 named!(action<&[u8], Action>, 
            dbg_dmp!( alt!(
              chain!(
                  function_val: command ~
                  space ~
-                 from_to_fingers_val: from_to_fingers,
+                 from_to_fingers_val: from_to_fingers ~
+                 modifiers: action_modifiers,
                  || 
                  {
-                     Action { 
-                         side: from_to_fingers_val.0,
-                         direction: from_to_fingers_val.1,
-                         num_fingers: from_to_fingers_val.2,
-                         function: function_val
-                     }
+                     build_action(from_to_fingers_val.0,
+                                  from_to_fingers_val.1,
+                                  from_to_fingers_val.2,
+                                  function_val,
+                                  modifiers)
                  }
            )
            | chain!(
                  from_to_fingers_val: from_to_fingers ~
                  space ~
-                 function_val: command,
+                 function_val: command ~
+                 modifiers: action_modifiers,
                  || 
                  {
-                     Action { 
-                         side: from_to_fingers_val.0,
-                         direction: from_to_fingers_val.1,
-                         num_fingers: from_to_fingers_val.2,
-                         function: function_val
-                     }
+                     build_action(from_to_fingers_val.0,
+                                  from_to_fingers_val.1,
+                                  from_to_fingers_val.2,
+                                  function_val,
+                                  modifiers)
                  }
            )
         )));
 
+/// Parses an action like "from left to right with two fingers run 'xeyes'".
+/// Returns None if it is not valid.
 pub fn parse_action(description:&[u8]) -> Option<Action> {
 match action(description) {
-        Done(_, action) => Some(action),
+        // Reject trailing garbage, so that e.g. a misspelled "in layer" does
+        // not silently make the action global.
+        Done(&[], mut action) => {
+            action.description = String::from_utf8_lossy(description).into_owned();
+            Some(action)
+        },
         _ => None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nom::IResult;
+    use std::{env, fs, process, thread};
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+
+    fn gesture() -> Gesture {
+        Gesture {
+            side: Side::Left,
+            direction: Direction::Right,
+            num_fingers: 2,
+            start_x: 5.4,
+            start_y: 400.6,
+            end_x: 300.5,
+            end_y: 410.0,
+            device_id: 11,
+        }
+    }
+
+    #[test]
+    fn expands_placeholders() {
+        let variables = vec![("side", String::from("left")), ("fingers", String::from("2"))];
+        for &(template, expected) in &[
+            ("notify-send {side}", "notify-send left"),
+            ("{side}{fingers}{side}", "left2left"),
+            ("{unknown} {} {side", "{unknown} {} {side"),
+            ("awk '{print $1}'", "awk '{print $1}'"),
+            ("{{side}} {{{side}", "{side}} {left"),
+            ("", ""),
+        ] {
+            assert_eq!(expand_placeholders(template, &variables), expected, "{}", template);
+        }
+    }
+
+    #[test]
+    fn gesture_variables_and_environment() {
+        let config = Config::fake(&[]);
+        config.device_names.borrow_mut().insert(11, String::from("ELAN Touchscreen"));
+        let mut on_accept_touch = |_, _, _| {};
+        let mut on_reject_touch = |_, _, _| {};
+        let mut on_gesture = |_: &mut GestureDetector, _: &Gesture| {};
+        let gesture_detector = GestureDetector::new(&config, &mut on_accept_touch, &mut on_reject_touch,
+                                                    &mut on_gesture);
+
+        let environment: Vec<(String, String)> = gesture_variables(&gesture_detector, &gesture()).iter()
+            .map(|&(name, ref value)| (environment_name(name), value.clone()))
+            .collect();
+        let expected: Vec<(String, String)> = [
+            ("EDGY_SIDE", "left"),
+            ("EDGY_DIRECTION", "right"),
+            ("EDGY_FINGERS", "2"),
+            ("EDGY_START_X", "5"),
+            ("EDGY_START_Y", "401"),
+            ("EDGY_END_X", "301"),
+            ("EDGY_END_Y", "410"),
+            // No output without X.
+            ("EDGY_OUTPUT", ""),
+            ("EDGY_DEVICE", "ELAN Touchscreen"),
+        ].iter().map(|&(name, value)| (String::from(name), String::from(value))).collect();
+        assert_eq!(environment, expected);
+
+        let variables = gesture_variables(&gesture_detector, &gesture());
+        assert_eq!(expand_placeholders("{side} {direction} {fingers} {start_x},{start_y} {end_x},{end_y} {device}", &variables),
+                   "left right 2 5,401 301,410 ELAN Touchscreen");
+    }
+
+    #[test]
+    fn quoted_string_removes_escaped_double_quotes() {
+        assert_eq!(quoted_string(b"\"echo \\\"hi\\\"\""), Done(&b""[..], String::from("echo \"hi\"")));
+    }
+
+    #[test]
+    fn quoted_string_removes_escaped_single_quotes() {
+        assert_eq!(quoted_string(b"'echo \\'hi\\' | tr h H'"), Done(&b""[..], String::from("echo 'hi' | tr h H")));
+    }
+
+    #[test]
+    fn quoted_string_keeps_other_escapes() {
+        assert_eq!(quoted_string(b"'printf \"a\\n\"'"), Done(&b""[..], String::from("printf \"a\\n\"")));
+        assert_eq!(quoted_string(b"'a\\\\\\'b'"), Done(&b""[..], String::from("a\\\\'b")));
+    }
+
+    #[test]
+    fn run_splits_the_command_the_shell_would_see() {
+        assert_eq!(command_words(b"\"echo \\\"hi\\\"\""), Done(&b""[..], vec![String::from("echo"), String::from("hi")]));
+        assert_eq!(command_words(b"'echo \\'hello world\\''"), Done(&b""[..], vec![String::from("echo"), String::from("hello world")]));
+        assert_eq!(command_words(b"'echo \"it\\'s\"'"), Done(&b""[..], vec![String::from("echo"), String::from("it's")]));
+    }
+
+    fn is_parsed(result: IResult<&[u8], ActionFunction>) -> bool {
+        match result {
+            Done(rest, _) => rest.is_empty(),
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn parses_single_instance_commands() {
+        assert!(is_parsed(run_once_command(b"run once 'onboard'")));
+        assert!(is_parsed(run_once_command(b"exec once command \"onboard --size 800x300\"")));
+        assert!(is_parsed(toggle_command(b"toggle 'onboard'")));
+        assert!(is_parsed(toggle_command(b"toggle cmd 'onboard'")));
+        assert!(!is_parsed(run_command(b"run once 'onboard'")));
+        assert!(!is_parsed(toggle_command(b"toggle touchscreen")));
+        assert!(!is_parsed(toggle_command(b"toggle 'unterminated")));
+        assert!(parse_action(b"from bottom to top with 3 fingers toggle 'onboard'").is_some());
+        assert!(parse_action(b"from bottom to top with 3 fingers run once 'onboard'").is_some());
+        assert!(parse_action(b"from bottom to top with 3 fingers toggle touchscreen").is_some());
+    }
+
+    // Runs the action as if its gesture was made.
+    fn trigger(config: &Config, function: &ActionFunction) {
+        let mut on_accept_touch = |_, _, _| {};
+        let mut on_reject_touch = |_, _, _| {};
+        let mut on_gesture = |_: &mut GestureDetector, _: &Gesture| {};
+        let mut gesture_detector = GestureDetector::new(config, &mut on_accept_touch, &mut on_reject_touch,
+                                                        &mut on_gesture);
+        function(&mut gesture_detector, &gesture());
+    }
+
+    // A command that appends its pid to a file and sleeps, and the file.
+    fn sleeper(name: &str) -> (Vec<String>, PathBuf) {
+        let path = env::temp_dir().join(format!("edgy-test-{}-{}", process::id(), name));
+        let _ = fs::remove_file(&path);
+        let script = format!("echo $$ >> {}; exec sleep 10", path.display());
+        (vec![String::from("/bin/sh"), String::from("-c"), script], path)
+    }
+
+    // Returns the pids written by the sleepers started so far, waiting for
+    // the expected number of them.
+    fn started(path: &PathBuf, expected: usize) -> Vec<libc::pid_t> {
+        let start = Instant::now();
+        loop {
+            let pids: Vec<libc::pid_t> = fs::read_to_string(path).unwrap_or_default()
+                .lines()
+                .map(|line| line.parse().unwrap())
+                .collect();
+            if pids.len() >= expected || start.elapsed() > Duration::from_secs(5) {
+                return pids;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn is_running(pid: libc::pid_t) -> bool {
+        unsafe { libc::kill(pid, 0) == 0 }
+    }
+
+    fn wait_for_exit(pid: libc::pid_t) {
+        let start = Instant::now();
+        while is_running(pid) && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!is_running(pid));
+    }
+
+    #[test]
+    fn run_once_does_not_start_a_second_instance() {
+        let config = Config::fake(&[]);
+        let (words, path) = sleeper("run-once");
+        let function = single_instance_command(words, false);
+
+        trigger(&config, &function);
+        let pids = started(&path, 1);
+        assert_eq!(pids.len(), 1);
+
+        trigger(&config, &function);
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(started(&path, 1), pids);
+        assert!(is_running(pids[0]));
+
+        // Once the instance has exited, the command is run again.
+        unsafe { libc::kill(pids[0], libc::SIGTERM); }
+        wait_for_exit(pids[0]);
+        trigger(&config, &function);
+        let pids = started(&path, 2);
+        assert_eq!(pids.len(), 2);
+
+        unsafe { libc::kill(pids[1], libc::SIGTERM); }
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn toggle_terminates_the_running_instance() {
+        let config = Config::fake(&[]);
+        let (words, path) = sleeper("toggle");
+        let function = single_instance_command(words, true);
+
+        trigger(&config, &function);
+        let pids = started(&path, 1);
+        assert_eq!(pids.len(), 1);
+
+        trigger(&config, &function);
+        wait_for_exit(pids[0]);
+        assert_eq!(started(&path, 1).len(), 1);
+
+        trigger(&config, &function);
+        let pids = started(&path, 2);
+        assert_eq!(pids.len(), 2);
+        assert!(is_running(pids[1]));
+
+        trigger(&config, &function);
+        wait_for_exit(pids[1]);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn parses_shell_and_run_actions() {
+        assert!(parse_action(b"from left to right with 2 fingers shell \"echo \\\"hi\\\" > /tmp/x\"").is_some());
+        assert!(parse_action(b"from left to right with 2 fingers shell 'echo \\'hi\\''").is_some());
+        assert!(parse_action(b"from left to right with 2 fingers run 'echo \"hi\"'").is_some());
+    }
+}
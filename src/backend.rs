@@ -0,0 +1,143 @@
+//! Sources of touch events. The gesture detector only sees TouchEvents, and
+//! accepts or rejects touches through the backend they came from, so the same
+//! detection logic works with XInput 2 or a script of events.
+
+use std::os::raw::c_int;
+use std::time::Duration;
+
+use libc;
+
+use gesture_detector::GestureDetector;
+
+/// A touch event, with the position of the touch in screen pixels.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum TouchEvent {
+    /// A touch started.
+    Begin {
+        /// Identifies the touch in later events, along with source_id. Touch
+        /// ids are only unique per device.
+        touch_id: i32,
+        /// The device the touch is accepted or rejected on.
+        device_id: i32,
+        /// The physical device the touch came from.
+        source_id: i32,
+        /// Horizontal position.
+        x: f64,
+        /// Vertical position.
+        y: f64,
+    },
+    /// A touch moved.
+    Update {
+        /// Identifies the touch, along with source_id.
+        touch_id: i32,
+        /// The physical device the touch came from.
+        source_id: i32,
+        /// Horizontal position.
+        x: f64,
+        /// Vertical position.
+        y: f64,
+    },
+    /// A touch was lifted.
+    End {
+        /// Identifies the touch, along with source_id.
+        touch_id: i32,
+        /// The physical device the touch came from.
+        source_id: i32,
+        /// Horizontal position.
+        x: f64,
+        /// Vertical position.
+        y: f64,
+    },
+}
+
+/// A source of touch events.
+pub trait InputBackend {
+    /// Starts receiving the touches of all devices.
+    fn grab(&mut self) -> Result<(), String>;
+
+    /// Stops receiving touches, e.g. before exiting.
+    fn ungrab(&mut self);
+
+    /// Waits until a touch event is available, one of the other file
+    /// descriptors is readable, or the timeout expires. Returns whether a
+    /// touch event is available.
+    fn wait(&mut self, timeout: Option<Duration>, other_fds: &[c_int]) -> bool;
+
+    /// Returns the next event, if one is available without waiting. Events
+    /// that are not touches are skipped, so this may return None even after
+    /// wait() returned true.
+    fn next_event(&mut self) -> Option<TouchEvent>;
+
+    /// Lets other applications see the touch, it is not part of a gesture.
+    fn reject_touch(&mut self, touch_id: i32, device_id: i32);
+
+    /// Keeps the touch from other applications, it is part of a gesture.
+    fn accept_touch(&mut self, touch_id: i32, device_id: i32);
+
+    /// Returns whether devices were added, removed, enabled or disabled since
+    /// the last call, in which case the devices in use should be selected
+    /// again.
+    fn devices_changed(&mut self) -> bool;
+}
+
+impl<B: InputBackend + ?Sized> InputBackend for Box<B> {
+    fn grab(&mut self) -> Result<(), String> {
+        (**self).grab()
+    }
+
+    fn ungrab(&mut self) {
+        (**self).ungrab()
+    }
+
+    fn wait(&mut self, timeout: Option<Duration>, other_fds: &[c_int]) -> bool {
+        (**self).wait(timeout, other_fds)
+    }
+
+    fn next_event(&mut self) -> Option<TouchEvent> {
+        (**self).next_event()
+    }
+
+    fn reject_touch(&mut self, touch_id: i32, device_id: i32) {
+        (**self).reject_touch(touch_id, device_id)
+    }
+
+    fn accept_touch(&mut self, touch_id: i32, device_id: i32) {
+        (**self).accept_touch(touch_id, device_id)
+    }
+
+    fn devices_changed(&mut self) -> bool {
+        (**self).devices_changed()
+    }
+}
+
+/// Passes the event to the gesture detector. Returns false for touches from
+/// devices the config does not listen to, which the caller should reject.
+pub fn handle_event(gesture_detector: &mut GestureDetector, event: TouchEvent) -> bool {
+    match event {
+        TouchEvent::Begin { touch_id, device_id, source_id, x, y } => {
+            if !gesture_detector.config.device_ids.borrow().contains(&source_id) {
+                return false;
+            }
+            gesture_detector.handle_touch_start(touch_id, device_id, source_id, x, y);
+        },
+        TouchEvent::Update { touch_id, source_id, x, y } => gesture_detector.handle_touch_update(touch_id, source_id, x, y),
+        TouchEvent::End { touch_id, source_id, x, y } => gesture_detector.handle_touch_end(touch_id, source_id, x, y),
+    }
+    true
+}
+
+/// Waits until one of the file descriptors is readable or the timeout
+/// expires, and returns whether each of them is readable.
+pub fn poll(fds: &[c_int], timeout: Option<Duration>) -> Vec<bool> {
+    let mut poll_fds: Vec<libc::pollfd> = fds.iter()
+        .map(|&fd| libc::pollfd { fd: fd, events: libc::POLLIN, revents: 0 })
+        .collect();
+    let timeout_ms = match timeout {
+        Some(timeout) => timeout.as_millis().min(i32::MAX as u128) as c_int,
+        None => -1,
+    };
+    if unsafe { libc::poll(poll_fds.as_mut_ptr(), poll_fds.len() as libc::nfds_t, timeout_ms) } <= 0 {
+        return vec![false; fds.len()];
+    }
+    poll_fds.iter().map(|poll_fd| poll_fd.revents != 0).collect()
+}
@@ -0,0 +1,84 @@
+// Controls a running edgy through its control socket (edgy -c).
+
+extern crate clap;
+extern crate edgy;
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process;
+
+use clap::{App, AppSettings, Arg, SubCommand};
+
+use edgy::control::default_socket_path;
+use edgy::json;
+
+fn main() {
+    let matches = App::new("edgyctl")
+        .version("0.1.0")
+        .about("Controls a running edgy, started with -c.")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(Arg::with_name("socket")
+             .short("s")
+             .help("Control socket of edgy, $XDG_RUNTIME_DIR/edgy.sock by default.")
+             .takes_value(true))
+        .subcommand(SubCommand::with_name("status")
+                    .about("Prints whether edgy is paused, the active layer, devices and bindings as JSON."))
+        .subcommand(SubCommand::with_name("trigger")
+                    .about("Runs the action given a name with \"named 'name'\".")
+                    .arg(Arg::with_name("name").required(true)))
+        .subcommand(SubCommand::with_name("pause")
+                    .about("Stops detecting gestures.")
+                    .arg(Arg::with_name("seconds")
+                         .help("Resume automatically after this many seconds.")))
+        .subcommand(SubCommand::with_name("resume")
+                    .about("Starts detecting gestures again."))
+        .subcommand(SubCommand::with_name("subscribe")
+                    .about("Prints gestures and state changes as JSON lines until interrupted."))
+        .get_matches();
+
+    let command = match matches.subcommand() {
+        ("trigger", Some(arguments)) => format!("trigger {}", arguments.value_of("name").unwrap()),
+        ("pause", Some(arguments)) => match arguments.value_of("seconds") {
+            Some(seconds) => format!("pause {}", seconds),
+            None => String::from("pause"),
+        },
+        (name, _) => String::from(name),
+    };
+
+    let path = matches.value_of("socket").map(PathBuf::from).unwrap_or_else(default_socket_path);
+    let mut stream = match UnixStream::connect(&path) {
+        Ok(stream) => stream,
+        Err(error) => {
+            eprintln!("Could not connect to {}: {}. Is edgy running with -c?", path.display(), error);
+            process::exit(1);
+        },
+    };
+
+    if let Err(error) = writeln!(stream, "{}", command) {
+        eprintln!("Could not send the command: {}", error);
+        process::exit(1);
+    }
+
+    // The first line is the response to the command, any further lines are
+    // events sent to subscribers.
+    let mut failed = None;
+    for line in BufReader::new(stream).lines() {
+        match line {
+            Ok(line) => {
+                if failed.is_none() {
+                    failed = Some(json::bool_field(&line, "ok") != Some(true));
+                }
+                println!("{}", line);
+            },
+            Err(error) => {
+                eprintln!("Could not read the response: {}", error);
+                process::exit(1);
+            },
+        }
+    }
+
+    if failed != Some(false) {
+        process::exit(1);
+    }
+}
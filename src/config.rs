@@ -1,15 +1,108 @@
+//! The settings edgy runs with, given on the command line.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
+
 use actions::Action;
+use device_spec::DeviceSpec;
+use xconn::XConnection;
+
+/// How touch input of a device is disabled for other applications.
+pub enum TouchDisableMethod {
+  /// Grab the device, so that only edgy receives its events.
+  Grab,
+  /// Set the XInput "Device Enabled" property of the device to 0.
+  DeviceEnabledProperty,
+  /// Grab the evdev device with EVIOCGRAB, used with the evdev backend.
+  EvdevGrab,
+}
 
+/// The settings the gesture detector, the actions and the event loop use.
 pub struct Config {
+  /// None without X, e.g. with the evdev backend under Wayland. Actions using
+  /// X then do nothing.
+  pub x11: Option<Rc<XConnection>>,
+
+  /// Size of the screen in pixels, touches are in the same coordinates.
   pub screen_width: f64,
+  /// See screen_width.
   pub screen_height: f64,
 
+  /// Distance from the edges, in pixels, within which a gesture must start.
   pub zone_width: f64,
 
+  /// How far, in pixels, a touch must move to make a swipe.
   pub minimum_distance: f64,
+  /// How far, in pixels, a touch may move in the wrong direction before the
+  /// gesture is ruined.
   pub detection_threshold: f64,
 
-  pub device_ids: Vec<i32>,
+  /// Which XInput devices to use, all touchscreens if empty. The devices are
+  /// selected again when XInput devices are added or removed.
+  pub device_specs: Vec<DeviceSpec>,
+  /// The devices in use, touches from other devices are rejected.
+  pub device_ids: RefCell<Vec<i32>>,
+  /// Names of the devices in use, for logging and notifications.
+  pub device_names: RefCell<HashMap<i32, String>>,
+  /// How the disable touchscreen action and touch blocking layers disable
+  /// the devices.
+  pub touch_disable_method: TouchDisableMethod,
 
+  /// The actions of all layers.
   pub actions: Vec<Action>,
+
+  /// The layer active at start.
+  pub initial_layer: String,
+  /// Touch input is disabled for other applications while these are active.
+  pub touch_blocking_layers: Vec<String>,
+
+  /// Show feedback while a gesture is being made.
+  pub show_overlay: bool,
+
+  /// Show desktop notifications when touch input is disabled or enabled.
+  pub notify_touch_changes: bool,
+  /// Show desktop notifications when a gesture is not recognized or has no
+  /// action.
+  pub notify_failed_gestures: bool,
+
+  /// Register on the session bus.
+  pub dbus_service: bool,
+
+  /// Listen for edgyctl commands on this socket.
+  pub control_socket: Option<PathBuf>,
+
+  /// Commands still running after this are terminated.
+  pub command_timeout: Option<Duration>,
+}
+
+#[cfg(test)]
+impl Config {
+  /// A config without X for a 1000x800 screen, using device 11, with the
+  /// actions parsed.
+  pub fn fake(actions: &[&str]) -> Config {
+    Config {
+      x11: None,
+      screen_width: 1000.0,
+      screen_height: 800.0,
+      zone_width: 50.0,
+      minimum_distance: 64.0,
+      detection_threshold: 24.0,
+      device_specs: Vec::new(),
+      device_ids: RefCell::new(vec![11]),
+      device_names: RefCell::new(HashMap::new()),
+      touch_disable_method: TouchDisableMethod::Grab,
+      actions: actions.iter().map(|action| ::actions::parse_action(action.as_bytes()).unwrap()).collect(),
+      initial_layer: String::from("normal"),
+      touch_blocking_layers: Vec::new(),
+      show_overlay: false,
+      notify_touch_changes: false,
+      notify_failed_gestures: false,
+      dbus_service: false,
+      control_socket: None,
+      command_timeout: None,
+    }
+  }
 }
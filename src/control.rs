@@ -0,0 +1,336 @@
+//! Control socket, used by edgyctl. Each connection sends one command line and
+//! gets one JSON line back, an object whose "ok" field tells whether the
+//! command succeeded, with "error" describing why not if it did not:
+//!
+//! ```text
+//! status            State of edgy, its devices and bindings.
+//! trigger NAME      Runs the action with the name.
+//! pause [SECONDS]   Stops detecting gestures, optionally for a while.
+//! resume            Starts detecting gestures again.
+//! subscribe         Keeps the connection open and sends events to it.
+//! ```
+
+use std::env;
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use libc;
+
+use actions::Action;
+use devicegrab::*;
+use events;
+use gesture_detector::{Gesture, GestureDetector};
+use json::Json;
+
+// How long to wait for a client to send its command or read the response.
+const CLIENT_TIMEOUT_MS: u64 = 1000;
+// Sanity limit for the length of commands.
+const MAX_COMMAND_LENGTH: usize = 4096;
+// Events are dropped for subscribers that do not keep up.
+const SUBSCRIBER_TIMEOUT_MS: u64 = 100;
+
+/// $XDG_RUNTIME_DIR/edgy.sock, or a socket in /tmp for the user if
+/// XDG_RUNTIME_DIR is not set.
+pub fn default_socket_path() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => Path::new(&dir).join("edgy.sock"),
+        None => PathBuf::from(format!("/tmp/edgy-{}.sock", unsafe { libc::getuid() })),
+    }
+}
+
+// A connection whose command has not been received completely yet.
+struct Client {
+    stream: UnixStream,
+    received: Vec<u8>,
+    // The client is dropped if its command has not arrived by then.
+    deadline: Instant,
+}
+
+/// The listening control socket. The socket file is removed when dropped.
+pub struct ControlSocket {
+    listener: UnixListener,
+    path: PathBuf,
+    clients: Vec<Client>,
+}
+
+fn error(message: &str) -> Json {
+    Json::Object(vec![("ok", Json::from(false)), ("error", Json::from(message))])
+}
+
+fn ok() -> Json {
+    Json::Object(vec![("ok", Json::from(true))])
+}
+
+fn binding(action: &Action) -> Json {
+    Json::Object(vec![
+        ("name", Json::from(action.name.clone())),
+        ("side", Json::from(action.side.to_string())),
+        ("direction", Json::from(action.direction.to_string())),
+        ("fingers", Json::from(action.num_fingers)),
+        ("layer", Json::from(action.layer.as_str())),
+        ("action", Json::from(action.description.as_str())),
+    ])
+}
+
+fn status(gesture_detector: &GestureDetector) -> Json {
+    let config = gesture_detector.config;
+    let devices = config.device_ids.borrow().iter()
+        .map(|&device_id| Json::Object(vec![
+            ("id", Json::from(device_id)),
+            ("name", Json::from(config.device_names.borrow().get(&device_id).cloned())),
+            ("touch_disabled", Json::from(is_touch_disabled(config, device_id))),
+        ]))
+        .collect();
+
+    Json::Object(vec![
+        ("ok", Json::from(true)),
+        ("paused", Json::from(gesture_detector.is_paused())),
+        ("layer", Json::from(gesture_detector.layers.current())),
+        ("devices", Json::Array(devices)),
+        ("bindings", Json::Array(config.actions.iter().map(binding).collect())),
+    ])
+}
+
+// Runs the action as if its gesture had been made.
+fn trigger(gesture_detector: &mut GestureDetector, name: &str) -> Json {
+    let config = gesture_detector.config;
+    let action = match config.actions.iter().find(|action| action.name.as_deref() == Some(name)) {
+        Some(action) => action,
+        None => return error(&format!("No action named {}.", name)),
+    };
+
+    let gesture = Gesture {
+        side: action.side,
+        direction: action.direction,
+        num_fingers: action.num_fingers,
+        start_x: 0.0,
+        start_y: 0.0,
+        end_x: 0.0,
+        end_y: 0.0,
+        device_id: config.device_ids.borrow().first().cloned().unwrap_or(0),
+    };
+    println!("Triggering action {}.", name);
+    events::emit("triggered", vec![("name", Json::from(name))]);
+    (action.function)(gesture_detector, &gesture);
+    ok()
+}
+
+fn pause(gesture_detector: &mut GestureDetector, seconds: Option<&str>) -> Json {
+    let duration = match seconds.map(|seconds| seconds.parse::<f64>()) {
+        None => None,
+        Some(Ok(seconds)) if seconds > 0.0 && seconds.is_finite() => Some(Duration::from_secs_f64(seconds)),
+        Some(_) => return error("Expected the number of seconds to pause for."),
+    };
+
+    println!("Pausing gesture detection.");
+    gesture_detector.pause(duration);
+    events::emit("paused", vec![("seconds", Json::from(duration.map(|duration| duration.as_secs_f64())))]);
+    ok()
+}
+
+/// Starts detecting gestures again and emits the resumed event.
+pub fn resume(gesture_detector: &mut GestureDetector) -> Json {
+    println!("Resuming gesture detection.");
+    gesture_detector.resume();
+    events::emit("resumed", vec![]);
+    ok()
+}
+
+// Reads what the client sent so far. Returns the command once its line is
+// complete, or the connection was closed after it.
+fn read_command(client: &mut Client) -> Result<Option<String>, ()> {
+    let mut buffer = [0; 256];
+    loop {
+        let closed = match client.stream.read(&mut buffer) {
+            Ok(0) => true,
+            Ok(count) => {
+                client.received.extend_from_slice(&buffer[..count]);
+                false
+            },
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return Err(()),
+        };
+
+        if let Some(end) = client.received.iter().position(|&byte| byte == b'\n') {
+            client.received.truncate(end);
+        }
+        else if !closed && client.received.len() <= MAX_COMMAND_LENGTH {
+            continue;
+        }
+        else if !closed || client.received.is_empty() {
+            return Err(());
+        }
+        return Ok(Some(String::from_utf8_lossy(&client.received).into_owned()));
+    }
+}
+
+fn handle_command(gesture_detector: &mut GestureDetector, mut stream: UnixStream, line: &str) {
+    // The response is short, it only waits for clients that do not read it.
+    let timeout = Some(Duration::from_millis(CLIENT_TIMEOUT_MS));
+    if stream.set_nonblocking(false).and_then(|_| stream.set_write_timeout(timeout)).is_err() {
+        return;
+    }
+
+    let mut parts = line.trim().splitn(2, ' ');
+    let response = match (parts.next().unwrap_or(""), parts.next().map(str::trim)) {
+        ("status", None) => status(gesture_detector),
+        ("trigger", Some(name)) => trigger(gesture_detector, name),
+        ("pause", seconds) => pause(gesture_detector, seconds),
+        ("resume", None) => resume(gesture_detector),
+        ("subscribe", None) => {
+            if writeln!(stream, "{}", ok()).is_ok() &&
+               stream.set_write_timeout(Some(Duration::from_millis(SUBSCRIBER_TIMEOUT_MS))).is_ok()
+            {
+                events::subscribe(stream);
+            }
+            return;
+        },
+        _ => error(&format!("Unknown command {}.", line.trim())),
+    };
+
+    // The client may already be gone, nothing to do about it.
+    let _ = writeln!(stream, "{}", response);
+}
+
+impl ControlSocket {
+    /// Listens on the path. A socket file left behind by an edgy that is no
+    /// longer running is replaced.
+    pub fn new(path: &Path) -> Result<ControlSocket, String> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(format!("{} is in use, is edgy already running?", path.display()));
+            }
+            // Left behind by an edgy that did not exit cleanly.
+            fs::remove_file(path).map_err(|e| format!("Could not remove {}: {}", path.display(), e))?;
+        }
+
+        let listener = UnixListener::bind(path).map_err(|e| format!("Could not listen on {}: {}", path.display(), e))?;
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+
+        println!("Listening for commands on {}.", path.display());
+        Ok(ControlSocket {
+            listener: listener,
+            path: path.to_path_buf(),
+            clients: Vec::new(),
+        })
+    }
+
+    /// File descriptors to wait on for new connections and commands.
+    pub fn fds(&self) -> Vec<RawFd> {
+        Some(self.listener.as_raw_fd()).into_iter()
+            .chain(self.clients.iter().map(|client| client.stream.as_raw_fd()))
+            .collect()
+    }
+
+    /// How long until a client that has not sent its command is dropped.
+    pub fn timeout(&self) -> Option<Duration> {
+        let now = Instant::now();
+        self.clients.iter()
+            .map(|client| if client.deadline > now { client.deadline - now } else { Duration::from_millis(0) })
+            .min()
+    }
+
+    /// Accepts the connections made so far and handles the commands received,
+    /// without waiting for either.
+    pub fn dispatch(&mut self, gesture_detector: &mut GestureDetector) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if stream.set_nonblocking(true).is_ok() {
+                        self.clients.push(Client {
+                            stream: stream,
+                            received: Vec::new(),
+                            deadline: Instant::now() + Duration::from_millis(CLIENT_TIMEOUT_MS),
+                        });
+                    }
+                },
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    println!("Could not accept a connection to the control socket: {}", e);
+                    break;
+                },
+            }
+        }
+
+        let now = Instant::now();
+        let mut index = 0;
+        while index < self.clients.len() {
+            match read_command(&mut self.clients[index]) {
+                Ok(None) if self.clients[index].deadline > now => index += 1,
+                Ok(Some(line)) => {
+                    let client = self.clients.remove(index);
+                    handle_command(gesture_detector, client.stream, &line);
+                },
+                // Closed, failed or timed out before sending a command.
+                Ok(None) | Err(()) => {
+                    self.clients.remove(index);
+                },
+            }
+        }
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> (Client, UnixStream) {
+        let (stream, other_end) = UnixStream::pair().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let client = Client {
+            stream: stream,
+            received: Vec::new(),
+            deadline: Instant::now(),
+        };
+        (client, other_end)
+    }
+
+    #[test]
+    fn responses_tell_whether_the_command_succeeded() {
+        assert_eq!(ok().to_string(), "{\"ok\":true}");
+        assert_eq!(error("No.").to_string(), "{\"ok\":false,\"error\":\"No.\"}");
+    }
+
+    #[test]
+    fn commands_are_buffered_across_reads() {
+        let (mut client, mut other_end) = client();
+        assert_eq!(read_command(&mut client), Ok(None));
+        other_end.write_all(b"pau").unwrap();
+        assert_eq!(read_command(&mut client), Ok(None));
+        other_end.write_all(b"se 5\nignored").unwrap();
+        assert_eq!(read_command(&mut client), Ok(Some(String::from("pause 5"))));
+    }
+
+    #[test]
+    fn command_may_end_with_the_connection() {
+        let (mut client, mut other_end) = client();
+        other_end.write_all(b"status").unwrap();
+        drop(other_end);
+        assert_eq!(read_command(&mut client), Ok(Some(String::from("status"))));
+    }
+
+    #[test]
+    fn closed_without_command_is_an_error() {
+        let (mut client, other_end) = client();
+        drop(other_end);
+        assert_eq!(read_command(&mut client), Err(()));
+    }
+
+    #[test]
+    fn overlong_command_is_an_error() {
+        let (mut client, mut other_end) = client();
+        other_end.write_all(&[b'a'; MAX_COMMAND_LENGTH + 1]).unwrap();
+        assert_eq!(read_command(&mut client), Err(()));
+    }
+}
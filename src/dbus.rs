@@ -0,0 +1,875 @@
+// A minimal D-Bus client, implementing just enough of the protocol to talk to
+// the session bus: authentication, marshalling of the basic and container
+// types, method calls and replies, and signals. Only little-endian messages are supported.
+// See https://dbus.freedesktop.org/doc/dbus-specification.html
+
+use std::collections::VecDeque;
+use std::env;
+use std::io::{Read, Write};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{SocketAddr, UnixStream};
+use std::str;
+use std::time::Duration;
+
+use libc;
+
+const BUS_NAME: &str = "org.freedesktop.DBus";
+const BUS_PATH: &str = "/org/freedesktop/DBus";
+
+// How long to wait for the bus or a reply before giving up.
+const TIMEOUT_MS: u64 = 1000;
+
+// Sanity limit for the size of incoming messages.
+const MAX_MESSAGE_SIZE: usize = 1 << 24;
+
+pub const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const SIGNAL: u8 = 4;
+
+const NO_REPLY_EXPECTED: u8 = 1;
+
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Byte(u8),
+    Boolean(bool),
+    Int32(i32),
+    UInt32(u32),
+    Double(f64),
+    String(String),
+    ObjectPath(String),
+    Signature(String),
+    // The signature of the elements is needed for empty arrays.
+    Array(String, Vec<Value>),
+    Struct(Vec<Value>),
+    DictEntry(Box<Value>, Box<Value>),
+    Variant(Box<Value>),
+}
+
+impl Value {
+    pub fn signature(&self) -> String {
+        match *self {
+            Value::Byte(_) => String::from("y"),
+            Value::Boolean(_) => String::from("b"),
+            Value::Int32(_) => String::from("i"),
+            Value::UInt32(_) => String::from("u"),
+            Value::Double(_) => String::from("d"),
+            Value::String(_) => String::from("s"),
+            Value::ObjectPath(_) => String::from("o"),
+            Value::Signature(_) => String::from("g"),
+            Value::Array(ref element, _) => format!("a{}", element),
+            Value::Struct(ref fields) => format!("({})", signature_of(fields)),
+            Value::DictEntry(ref key, ref value) => format!("{{{}{}}}", key.signature(), value.signature()),
+            Value::Variant(_) => String::from("v"),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::String(ref s) | Value::ObjectPath(ref s) | Value::Signature(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        match *self {
+            Value::UInt32(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+pub fn signature_of(values: &[Value]) -> String {
+    values.iter().map(Value::signature).collect()
+}
+
+fn alignment(type_code: u8) -> usize {
+    match type_code {
+        b'y' | b'g' | b'v' => 1,
+        b'x' | b't' | b'd' | b'(' | b'{' => 8,
+        _ => 4,
+    }
+}
+
+// Splits the first complete type off a signature.
+fn split_type(signature: &[u8]) -> Result<(&[u8], &[u8]), String> {
+    let end = match signature.first() {
+        None => return Err(String::from("Signature ended unexpectedly.")),
+        Some(&b'a') => {
+            let (element, _) = split_type(&signature[1..])?;
+            1 + element.len()
+        },
+        Some(&open) if open == b'(' || open == b'{' => {
+            let close = if open == b'(' { b')' } else { b'}' };
+            let mut depth = 0;
+            let mut end = None;
+            for (i, &c) in signature.iter().enumerate() {
+                if c == open {
+                    depth += 1;
+                }
+                else if c == close {
+                    depth -= 1;
+                    if depth == 0 {
+                        end = Some(i + 1);
+                        break;
+                    }
+                }
+            }
+            match end {
+                Some(end) => end,
+                None => return Err(String::from("Unbalanced signature.")),
+            }
+        },
+        Some(_) => 1,
+    };
+    Ok(signature.split_at(end))
+}
+
+struct Writer {
+    buffer: Vec<u8>,
+}
+
+impl Writer {
+    fn align(&mut self, alignment: usize) {
+        while !self.buffer.len().is_multiple_of(alignment) {
+            self.buffer.push(0);
+        }
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.align(4);
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_string(&mut self, value: &str) {
+        self.write_u32(value.len() as u32);
+        self.buffer.extend_from_slice(value.as_bytes());
+        self.buffer.push(0);
+    }
+
+    fn write_signature(&mut self, value: &str) {
+        self.buffer.push(value.len() as u8);
+        self.buffer.extend_from_slice(value.as_bytes());
+        self.buffer.push(0);
+    }
+
+    fn write_value(&mut self, value: &Value) {
+        match *value {
+            Value::Byte(value) => self.buffer.push(value),
+            Value::Boolean(value) => self.write_u32(value as u32),
+            Value::Int32(value) => self.write_u32(value as u32),
+            Value::UInt32(value) => self.write_u32(value),
+            Value::Double(value) => {
+                self.align(8);
+                self.buffer.extend_from_slice(&value.to_bits().to_le_bytes());
+            },
+            Value::String(ref value) | Value::ObjectPath(ref value) => self.write_string(value),
+            Value::Signature(ref value) => self.write_signature(value),
+            Value::Array(ref element, ref values) => {
+                self.write_u32(0);
+                let length_position = self.buffer.len() - 4;
+                // The length does not include the padding before the first element.
+                self.align(alignment(element.as_bytes()[0]));
+                let start = self.buffer.len();
+                for value in values {
+                    self.write_value(value);
+                }
+                let length = (self.buffer.len() - start) as u32;
+                self.buffer[length_position..length_position + 4].copy_from_slice(&length.to_le_bytes());
+            },
+            Value::Struct(ref fields) => {
+                self.align(8);
+                for field in fields {
+                    self.write_value(field);
+                }
+            },
+            Value::DictEntry(ref key, ref value) => {
+                self.align(8);
+                self.write_value(key);
+                self.write_value(value);
+            },
+            Value::Variant(ref value) => {
+                self.write_signature(&value.signature());
+                self.write_value(value);
+            },
+        }
+    }
+}
+
+struct Reader<'a> {
+    buffer: &'a [u8],
+    position: usize,
+}
+
+impl <'a>Reader<'a> {
+    fn align(&mut self, alignment: usize) {
+        self.position = self.position.div_ceil(alignment) * alignment;
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.position + count > self.buffer.len() {
+            return Err(String::from("Message ended unexpectedly."));
+        }
+        let bytes = &self.buffer[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, String> {
+        self.align(4);
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_string(&mut self, length: usize) -> Result<String, String> {
+        let bytes = self.take(length + 1)?;
+        match str::from_utf8(&bytes[..length]) {
+            Ok(s) => Ok(String::from(s)),
+            Err(_) => Err(String::from("String is not valid UTF-8.")),
+        }
+    }
+
+    fn read_value(&mut self, signature: &[u8]) -> Result<Value, String> {
+        let value = match signature[0] {
+            b'y' => Value::Byte(self.take(1)?[0]),
+            b'b' => Value::Boolean(self.read_u32()? != 0),
+            b'i' => Value::Int32(self.read_u32()? as i32),
+            b'u' => Value::UInt32(self.read_u32()?),
+            b'd' => {
+                self.align(8);
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(self.take(8)?);
+                Value::Double(f64::from_bits(u64::from_le_bytes(bytes)))
+            },
+            b's' | b'o' => {
+                let length = self.read_u32()? as usize;
+                let s = self.read_string(length)?;
+                if signature[0] == b's' { Value::String(s) } else { Value::ObjectPath(s) }
+            },
+            b'g' => {
+                let length = self.take(1)?[0] as usize;
+                Value::Signature(self.read_string(length)?)
+            },
+            b'a' => {
+                let length = self.read_u32()? as usize;
+                let element = &signature[1..];
+                self.align(alignment(element[0]));
+                let end = self.position + length;
+                if end > self.buffer.len() {
+                    return Err(String::from("Message ended unexpectedly."));
+                }
+                let mut values = Vec::new();
+                while self.position < end {
+                    values.push(self.read_value(element)?);
+                }
+                Value::Array(String::from_utf8_lossy(element).into_owned(), values)
+            },
+            b'(' => {
+                self.align(8);
+                let mut fields = Vec::new();
+                let mut rest = &signature[1..signature.len() - 1];
+                while !rest.is_empty() {
+                    let (field, remaining) = split_type(rest)?;
+                    fields.push(self.read_value(field)?);
+                    rest = remaining;
+                }
+                Value::Struct(fields)
+            },
+            b'{' => {
+                self.align(8);
+                let (key, rest) = split_type(&signature[1..signature.len() - 1])?;
+                let key = self.read_value(key)?;
+                let value = self.read_value(rest)?;
+                Value::DictEntry(Box::new(key), Box::new(value))
+            },
+            b'v' => {
+                let length = self.take(1)?[0] as usize;
+                let inner = self.read_string(length)?;
+                let (inner, _) = split_type(inner.as_bytes())?;
+                Value::Variant(Box::new(self.read_value(inner)?))
+            },
+            other => return Err(format!("Unsupported type code {}.", other as char)),
+        };
+        Ok(value)
+    }
+
+    fn read_values(&mut self, signature: &[u8]) -> Result<Vec<Value>, String> {
+        let mut values = Vec::new();
+        let mut rest = signature;
+        while !rest.is_empty() {
+            let (value_type, remaining) = split_type(rest)?;
+            values.push(self.read_value(value_type)?);
+            rest = remaining;
+        }
+        Ok(values)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Message {
+    pub message_type: u8,
+    pub flags: u8,
+    pub serial: u32,
+    pub path: Option<String>,
+    pub interface: Option<String>,
+    pub member: Option<String>,
+    pub error_name: Option<String>,
+    pub reply_serial: Option<u32>,
+    pub destination: Option<String>,
+    pub sender: Option<String>,
+    pub body: Vec<Value>,
+}
+
+impl Message {
+    pub fn method_call(destination: &str, path: &str, interface: &str, member: &str, body: Vec<Value>) -> Message {
+        Message {
+            message_type: METHOD_CALL,
+            path: Some(String::from(path)),
+            interface: Some(String::from(interface)),
+            member: Some(String::from(member)),
+            destination: Some(String::from(destination)),
+            body: body,
+            ..Message::default()
+        }
+    }
+
+    pub fn signal(path: &str, interface: &str, member: &str, body: Vec<Value>) -> Message {
+        Message {
+            message_type: SIGNAL,
+            flags: NO_REPLY_EXPECTED,
+            path: Some(String::from(path)),
+            interface: Some(String::from(interface)),
+            member: Some(String::from(member)),
+            body: body,
+            ..Message::default()
+        }
+    }
+
+    pub fn method_return(call: &Message, body: Vec<Value>) -> Message {
+        Message {
+            message_type: METHOD_RETURN,
+            flags: NO_REPLY_EXPECTED,
+            reply_serial: Some(call.serial),
+            destination: call.sender.clone(),
+            body: body,
+            ..Message::default()
+        }
+    }
+
+    pub fn error_reply(call: &Message, error_name: &str, text: &str) -> Message {
+        Message {
+            message_type: ERROR,
+            flags: NO_REPLY_EXPECTED,
+            error_name: Some(String::from(error_name)),
+            reply_serial: Some(call.serial),
+            destination: call.sender.clone(),
+            body: vec![Value::String(String::from(text))],
+            ..Message::default()
+        }
+    }
+
+    pub fn expects_reply(&self) -> bool {
+        self.message_type == METHOD_CALL && self.flags & NO_REPLY_EXPECTED == 0
+    }
+
+    // Returns the error message, if this is an error reply.
+    pub fn error(&self) -> Option<String> {
+        if self.message_type != ERROR {
+            return None;
+        }
+        let name = self.error_name.clone().unwrap_or_default();
+        match self.body.first().and_then(Value::as_str) {
+            Some(text) => Some(format!("{}: {}", name, text)),
+            None => Some(name),
+        }
+    }
+
+    fn marshal(&self) -> Vec<u8> {
+        let mut fields = Vec::new();
+        {
+            let mut add_field = |code: u8, value: Value| {
+                fields.push(Value::Struct(vec![Value::Byte(code), Value::Variant(Box::new(value))]));
+            };
+            if let Some(ref path) = self.path {
+                add_field(FIELD_PATH, Value::ObjectPath(path.clone()));
+            }
+            if let Some(ref interface) = self.interface {
+                add_field(FIELD_INTERFACE, Value::String(interface.clone()));
+            }
+            if let Some(ref member) = self.member {
+                add_field(FIELD_MEMBER, Value::String(member.clone()));
+            }
+            if let Some(ref error_name) = self.error_name {
+                add_field(FIELD_ERROR_NAME, Value::String(error_name.clone()));
+            }
+            if let Some(reply_serial) = self.reply_serial {
+                add_field(FIELD_REPLY_SERIAL, Value::UInt32(reply_serial));
+            }
+            if let Some(ref destination) = self.destination {
+                add_field(FIELD_DESTINATION, Value::String(destination.clone()));
+            }
+            if !self.body.is_empty() {
+                add_field(FIELD_SIGNATURE, Value::Signature(signature_of(&self.body)));
+            }
+        }
+
+        let mut body = Writer { buffer: Vec::new() };
+        for value in &self.body {
+            body.write_value(value);
+        }
+
+        let mut writer = Writer { buffer: vec![b'l', self.message_type, self.flags, 1] };
+        writer.write_u32(body.buffer.len() as u32);
+        writer.write_u32(self.serial);
+        writer.write_value(&Value::Array(String::from("(yv)"), fields));
+        writer.align(8);
+        writer.buffer.extend_from_slice(&body.buffer);
+        writer.buffer
+    }
+
+    fn unmarshal(buffer: &[u8]) -> Result<Message, String> {
+        if buffer[0] != b'l' {
+            return Err(String::from("Big-endian messages are not supported."));
+        }
+
+        let mut reader = Reader { buffer: buffer, position: 4 };
+        let body_length = reader.read_u32()? as usize;
+        let mut message = Message {
+            message_type: buffer[1],
+            flags: buffer[2],
+            serial: reader.read_u32()?,
+            ..Message::default()
+        };
+
+        let mut signature = String::new();
+        if let Value::Array(_, fields) = reader.read_value(b"a(yv)")? {
+            for field in fields {
+                let (code, value) = match field {
+                    Value::Struct(mut parts) => match (parts.pop(), parts.pop()) {
+                        (Some(Value::Variant(value)), Some(Value::Byte(code))) => (code, *value),
+                        _ => continue,
+                    },
+                    _ => continue,
+                };
+                let text = value.as_str().map(String::from);
+                match code {
+                    FIELD_PATH => message.path = text,
+                    FIELD_INTERFACE => message.interface = text,
+                    FIELD_MEMBER => message.member = text,
+                    FIELD_ERROR_NAME => message.error_name = text,
+                    FIELD_REPLY_SERIAL => message.reply_serial = value.as_u32(),
+                    FIELD_DESTINATION => message.destination = text,
+                    FIELD_SENDER => message.sender = text,
+                    FIELD_SIGNATURE => signature = text.unwrap_or_default(),
+                    _ => {},
+                }
+            }
+        }
+
+        reader.align(8);
+        let body_start = reader.position;
+        let mut body = Reader { buffer: &buffer[body_start..body_start + body_length], position: 0 };
+        message.body = body.read_values(signature.as_bytes())?;
+        Ok(message)
+    }
+}
+
+fn read_message(stream: &mut dyn Read) -> Result<Message, String> {
+    let mut fixed = [0; 16];
+    stream.read_exact(&mut fixed).map_err(|e| e.to_string())?;
+
+    let body_length = u32::from_le_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]) as usize;
+    let fields_length = u32::from_le_bytes([fixed[12], fixed[13], fixed[14], fixed[15]]) as usize;
+    let header_length = (16 + fields_length).div_ceil(8) * 8;
+    if fixed[0] != b'l' || header_length + body_length > MAX_MESSAGE_SIZE {
+        return Err(String::from("Unsupported message."));
+    }
+
+    let mut buffer = fixed.to_vec();
+    buffer.resize(header_length + body_length, 0);
+    stream.read_exact(&mut buffer[16..]).map_err(|e| e.to_string())?;
+    Message::unmarshal(&buffer)
+}
+
+// Decodes the %xx escapes used in D-Bus addresses.
+fn unescape_address_value(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Some(byte) = str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                result.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        result.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+// Connects to the first usable unix: address in e.g.
+// "unix:path=/run/user/1000/bus" or "unix:abstract=/tmp/dbus-x,guid=y".
+fn connect_address(addresses: &str) -> Result<UnixStream, String> {
+    let mut error = format!("No supported address in {}.", addresses);
+
+    for address in addresses.split(';') {
+        if !address.starts_with("unix:") {
+            continue;
+        }
+        for pair in address["unix:".len()..].split(',') {
+            let mut parts = pair.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key, unescape_address_value(value)),
+                _ => continue,
+            };
+            let result = match key {
+                "path" => UnixStream::connect(&value),
+                "abstract" => SocketAddr::from_abstract_name(value.as_bytes())
+                    .and_then(|socket_address| UnixStream::connect_addr(&socket_address)),
+                _ => continue,
+            };
+            match result {
+                Ok(stream) => return Ok(stream),
+                Err(e) => error = format!("Could not connect to {}: {}", address, e),
+            }
+        }
+    }
+
+    Err(error)
+}
+
+pub struct Connection {
+    stream: UnixStream,
+    next_serial: u32,
+    pub unique_name: String,
+    // Messages received while waiting for a reply.
+    queue: VecDeque<Message>,
+}
+
+impl Connection {
+    pub fn session() -> Result<Connection, String> {
+        let address = match env::var("DBUS_SESSION_BUS_ADDRESS") {
+            Ok(address) => address,
+            Err(_) => return Err(String::from("DBUS_SESSION_BUS_ADDRESS is not set.")),
+        };
+        Connection::open(&address)
+    }
+
+    pub fn open(address: &str) -> Result<Connection, String> {
+        Connection::from_stream(connect_address(address)?)
+    }
+
+    // Authenticates and registers on the bus the stream is connected to.
+    fn from_stream(stream: UnixStream) -> Result<Connection, String> {
+        let timeout = Some(Duration::from_millis(TIMEOUT_MS));
+        stream.set_read_timeout(timeout).and_then(|_| stream.set_write_timeout(timeout))
+            .map_err(|e| e.to_string())?;
+
+        let mut connection = Connection {
+            stream: stream,
+            next_serial: 1,
+            unique_name: String::new(),
+            queue: VecDeque::new(),
+        };
+        connection.authenticate()?;
+
+        let reply = connection.call(BUS_NAME, BUS_PATH, BUS_NAME, "Hello", vec![])?;
+        connection.unique_name = reply.first().and_then(Value::as_str).unwrap_or_default().to_string();
+        Ok(connection)
+    }
+
+    fn read_line(&mut self) -> Result<String, String> {
+        let mut line = Vec::new();
+        let mut byte = [0];
+        while !line.ends_with(b"\r\n") {
+            self.stream.read_exact(&mut byte).map_err(|e| e.to_string())?;
+            line.push(byte[0]);
+        }
+        Ok(String::from_utf8_lossy(&line[..line.len() - 2]).into_owned())
+    }
+
+    // Authenticates as the user running edgy, with the credentials of the socket.
+    fn authenticate(&mut self) -> Result<(), String> {
+        let uid = unsafe { libc::getuid() }.to_string();
+        let hex_uid: String = uid.bytes().map(|b| format!("{:02x}", b)).collect();
+
+        self.stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", hex_uid).as_bytes())
+            .map_err(|e| e.to_string())?;
+        let response = self.read_line()?;
+        if !response.starts_with("OK ") {
+            return Err(format!("Authentication failed: {}", response));
+        }
+        self.stream.write_all(b"BEGIN\r\n").map_err(|e| e.to_string())
+    }
+
+    // Sends the message, returning its serial.
+    pub fn send(&mut self, mut message: Message) -> Result<u32, String> {
+        message.serial = self.next_serial;
+        self.next_serial += 1;
+        self.stream.write_all(&message.marshal()).map_err(|e| e.to_string())?;
+        Ok(message.serial)
+    }
+
+    fn read_message(&mut self) -> Result<Message, String> {
+        read_message(&mut self.stream)
+    }
+
+    // Calls the method and waits for its reply.
+    pub fn call(&mut self, destination: &str, path: &str, interface: &str, member: &str, args: Vec<Value>)
+        -> Result<Vec<Value>, String>
+    {
+        let serial = self.send(Message::method_call(destination, path, interface, member, args))?;
+        loop {
+            let message = self.read_message()?;
+            if message.reply_serial != Some(serial) {
+                self.queue.push_back(message);
+                continue;
+            }
+            return match message.error() {
+                Some(error) => Err(error),
+                None => Ok(message.body),
+            };
+        }
+    }
+
+    pub fn emit_signal(&mut self, path: &str, interface: &str, member: &str, args: Vec<Value>) -> Result<(), String> {
+        self.send(Message::signal(path, interface, member, args)).map(|_| ())
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+
+    // Returns the next received message, or None if there is none, without
+    // waiting.
+    pub fn poll_message(&mut self) -> Result<Option<Message>, String> {
+        if let Some(message) = self.queue.pop_front() {
+            return Ok(Some(message));
+        }
+
+        let mut poll_fd = libc::pollfd {
+            fd: self.fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut poll_fd, 1, 0) } <= 0 {
+            return Ok(None);
+        }
+        if poll_fd.revents & libc::POLLIN == 0 {
+            return Err(String::from("Connection to the bus was closed."));
+        }
+        self.read_message().map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn marshal_values(values: &[Value]) -> Vec<u8> {
+        let mut writer = Writer { buffer: Vec::new() };
+        for value in values {
+            writer.write_value(value);
+        }
+        writer.buffer
+    }
+
+    #[test]
+    fn values_are_aligned_to_their_size() {
+        assert_eq!(marshal_values(&[Value::Byte(1), Value::UInt32(2), Value::Byte(3), Value::Double(1.0)]),
+                   vec![1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xf0, 0x3f]);
+    }
+
+    #[test]
+    fn array_length_excludes_padding_before_first_element() {
+        assert_eq!(marshal_values(&[Value::Array(String::from("d"), vec![Value::Double(0.0)])]),
+                   vec![8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // Empty arrays are padded too.
+        assert_eq!(marshal_values(&[Value::Array(String::from("(yv)"), vec![])]),
+                   vec![0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn strings_and_signatures_are_nul_terminated() {
+        assert_eq!(marshal_values(&[Value::String(String::from("ab")), Value::Signature(String::from("s"))]),
+                   vec![2, 0, 0, 0, b'a', b'b', 0, 1, b's', 0]);
+    }
+
+    #[test]
+    fn reader_aligns_to_the_next_multiple() {
+        let buffer = [0; 16];
+        let mut reader = Reader { buffer: &buffer, position: 0 };
+        reader.align(8);
+        assert_eq!(reader.position, 0);
+        reader.position = 1;
+        reader.align(4);
+        assert_eq!(reader.position, 4);
+        reader.position = 9;
+        reader.align(8);
+        assert_eq!(reader.position, 16);
+    }
+
+    #[test]
+    fn messages_survive_a_round_trip() {
+        let mut message = Message::method_call("org.example.Dest", "/org/example", "org.example.Iface", "Method", vec![
+            Value::Byte(7),
+            Value::Double(-2.5),
+            Value::Boolean(true),
+            Value::String(String::from("text")),
+            Value::Array(String::from("{sv}"), vec![
+                Value::DictEntry(Box::new(Value::String(String::from("key"))),
+                                 Box::new(Value::Variant(Box::new(Value::Int32(-3))))),
+            ]),
+            Value::Array(String::from("s"), vec![]),
+            Value::Struct(vec![Value::Byte(1), Value::UInt32(2)]),
+        ]);
+        message.serial = 5;
+
+        let buffer = message.marshal();
+        let mut stream: &[u8] = &buffer;
+        let result = read_message(&mut stream).unwrap();
+        assert!(stream.is_empty());
+        assert_eq!(result.message_type, METHOD_CALL);
+        assert_eq!(result.serial, 5);
+        assert_eq!(result.destination, message.destination);
+        assert_eq!(result.path, message.path);
+        assert_eq!(result.interface, message.interface);
+        assert_eq!(result.member, message.member);
+        assert_eq!(result.body, message.body);
+    }
+
+    #[test]
+    fn header_is_padded_before_the_body() {
+        let message = Message::signal("/a", "a.b", "C", vec![Value::Byte(9)]);
+        let buffer = message.marshal();
+        assert_eq!(buffer.len() % 8, 1);
+        assert_eq!(buffer[buffer.len() - 1], 9);
+    }
+
+    // Plays the bus on the other end of a socket pair, handling authentication
+    // and replying to Hello, then runs the rest of the test on the bus side.
+    fn with_bus<F>(auth_response: &'static str, bus: F) -> Result<Connection, String>
+        where F: FnOnce(&mut UnixStream) + Send + 'static
+    {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let handle = thread::spawn(move || {
+            let mut auth = Vec::new();
+            let mut byte = [0];
+            while !auth.ends_with(b"\r\n") {
+                server.read_exact(&mut byte).unwrap();
+                auth.push(byte[0]);
+            }
+            assert!(auth.starts_with(b"\0AUTH EXTERNAL "));
+            server.write_all(auth_response.as_bytes()).unwrap();
+            if !auth_response.starts_with("OK ") {
+                return;
+            }
+
+            let mut begin = [0; 7];
+            server.read_exact(&mut begin).unwrap();
+            assert_eq!(&begin, b"BEGIN\r\n");
+
+            let hello = read_message(&mut server).unwrap();
+            assert_eq!(hello.member.as_deref(), Some("Hello"));
+            let mut reply = Message::method_return(&hello, vec![Value::String(String::from(":1.42"))]);
+            reply.serial = 1;
+            server.write_all(&reply.marshal()).unwrap();
+
+            bus(&mut server);
+        });
+        let connection = Connection::from_stream(client);
+        handle.join().unwrap();
+        connection
+    }
+
+    #[test]
+    fn authenticates_and_says_hello() {
+        let connection = with_bus("OK 0123456789abcdef\r\n", |_| {}).unwrap();
+        assert_eq!(connection.unique_name, ":1.42");
+    }
+
+    #[test]
+    fn rejected_authentication_is_an_error() {
+        match with_bus("REJECTED EXTERNAL\r\n", |_| {}) {
+            Err(error) => assert_eq!(error, "Authentication failed: REJECTED EXTERNAL"),
+            Ok(_) => panic!("authentication should fail"),
+        }
+    }
+
+    #[test]
+    fn replies_are_matched_by_serial() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut connection = Connection {
+            stream: client,
+            next_serial: 1,
+            unique_name: String::new(),
+            queue: VecDeque::new(),
+        };
+        let handle = thread::spawn(move || {
+            let first = read_message(&mut server).unwrap();
+            let second = read_message(&mut server).unwrap();
+            let mut messages = [
+                Message::signal("/a", "a.b", "Changed", vec![]),
+                Message::method_return(&second, vec![Value::UInt32(2)]),
+                Message::error_reply(&first, "a.b.Error", "failed"),
+            ];
+            for (serial, message) in messages.iter_mut().enumerate() {
+                message.serial = serial as u32 + 10;
+                server.write_all(&message.marshal()).unwrap();
+            }
+            server
+        });
+
+        connection.send(Message::method_call("a.b", "/a", "a.b", "First", vec![])).unwrap();
+        assert_eq!(connection.call("a.b", "/a", "a.b", "Second", vec![]), Ok(vec![Value::UInt32(2)]));
+        let _server = handle.join().unwrap();
+
+        // The signal and the error reply to the first call arrived while
+        // waiting, and are kept in order.
+        let signal = connection.poll_message().unwrap().unwrap();
+        assert_eq!(signal.member.as_deref(), Some("Changed"));
+        let error = connection.poll_message().unwrap().unwrap();
+        assert_eq!(error.reply_serial, Some(1));
+        assert_eq!(error.error(), Some(String::from("a.b.Error: failed")));
+        assert!(connection.poll_message().unwrap().is_none());
+    }
+
+    #[test]
+    fn error_reply_fails_the_call() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut connection = Connection {
+            stream: client,
+            next_serial: 1,
+            unique_name: String::new(),
+            queue: VecDeque::new(),
+        };
+        let handle = thread::spawn(move || {
+            let call = read_message(&mut server).unwrap();
+            let mut reply = Message::error_reply(&call, "a.b.Error", "no");
+            reply.serial = 1;
+            server.write_all(&reply.marshal()).unwrap();
+            server
+        });
+        assert_eq!(connection.call("a.b", "/a", "a.b", "M", vec![]), Err(String::from("a.b.Error: no")));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn address_values_are_unescaped() {
+        assert_eq!(unescape_address_value("/tmp/dbus%2dx%"), "/tmp/dbus-x%");
+    }
+}
@@ -0,0 +1,244 @@
+// Exposes edgy on the session bus, so that other programs can observe
+// gestures and control edgy. E.g.
+//
+//   dbus-monitor "type='signal',interface='info.njomotys.Edgy'"
+//   dbus-send --session --print-reply --dest=info.njomotys.Edgy /info/njomotys/Edgy info.njomotys.Edgy.DisableTouch
+
+use std::os::unix::io::RawFd;
+
+use actions::Action;
+use config::TouchDisableMethod;
+use dbus::{self, Connection, Message, Value};
+use devicegrab::*;
+use gesture_detector::{Gesture, GestureDetector};
+
+const NAME: &str = "info.njomotys.Edgy";
+const PATH: &str = "/info/njomotys/Edgy";
+const INTERFACE: &str = "info.njomotys.Edgy";
+
+const INTROSPECTABLE_INTERFACE: &str = "org.freedesktop.DBus.Introspectable";
+
+// Flag of RequestName: fail instead of waiting for the name to be released.
+const DO_NOT_QUEUE: u32 = 4;
+const PRIMARY_OWNER: u32 = 1;
+
+const ERROR_UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+const ERROR_UNKNOWN_OBJECT: &str = "org.freedesktop.DBus.Error.UnknownObject";
+const ERROR_INVALID_ARGS: &str = "org.freedesktop.DBus.Error.InvalidArgs";
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="info.njomotys.Edgy">
+    <signal name="Gesture">
+      <arg name="side" type="s"/>
+      <arg name="direction" type="s"/>
+      <arg name="fingers" type="u"/>
+    </signal>
+    <method name="EnableTouch"/>
+    <method name="DisableTouch"/>
+    <method name="ToggleTouch"/>
+    <method name="SwitchLayer">
+      <arg name="layer" type="s" direction="in"/>
+    </method>
+    <method name="GetLayer">
+      <arg name="layer" type="s" direction="out"/>
+    </method>
+    <method name="ListBindings">
+      <arg name="bindings" type="a(ssuss)" direction="out"/>
+    </method>
+    <method name="ReloadConfig"/>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+type CallResult = Result<Vec<Value>, (&'static str, String)>;
+
+pub struct Service {
+    // None after the connection to the bus has failed.
+    connection: Option<Connection>,
+}
+
+fn binding(action: &Action) -> Value {
+    Value::Struct(vec![
+        Value::String(action.side.to_string()),
+        Value::String(action.direction.to_string()),
+        Value::UInt32(action.num_fingers),
+        Value::String(action.layer.clone()),
+        Value::String(action.description.clone()),
+    ])
+}
+
+// Applies the configuration again as at start: the devices are selected again
+// and the initial layer becomes active. The actions are given on the command
+// line and stay the same.
+fn reload_config(gesture_detector: &mut GestureDetector) {
+    let config = gesture_detector.config;
+    match config.touch_disable_method {
+        // The evdev backend uses the devices it was started with.
+        TouchDisableMethod::EvdevGrab => {},
+        _ => {
+            let added = update_devices(config);
+            gesture_detector.layers.block_touch(config, &added);
+        },
+    }
+    gesture_detector.layers.switch(config, &config.initial_layer);
+}
+
+fn handle_call(gesture_detector: &mut GestureDetector, message: &Message) -> CallResult {
+    if message.path.as_deref() != Some(PATH) {
+        return Err((ERROR_UNKNOWN_OBJECT, format!("No object at {}.", message.path.as_deref().unwrap_or(""))));
+    }
+
+    let config = gesture_detector.config;
+    let member = message.member.as_deref().unwrap_or("");
+    match message.interface.as_deref() {
+        Some(INTROSPECTABLE_INTERFACE) if member == "Introspect" => {
+            return Ok(vec![Value::String(String::from(INTROSPECTION))]);
+        },
+        // The interface is optional in method calls.
+        Some(INTERFACE) | None => {},
+        Some(interface) => return Err((ERROR_UNKNOWN_METHOD, format!("Unknown method {}.{}.", interface, member))),
+    }
+
+    match member {
+        "EnableTouch" => enable_touch(config, &config.device_ids.borrow()),
+        "DisableTouch" => disable_touch(config, &config.device_ids.borrow()),
+        "ToggleTouch" => toggle_touch(config, &config.device_ids.borrow()),
+        "SwitchLayer" => match message.body.first().and_then(Value::as_str) {
+            Some(layer) => gesture_detector.layers.switch(config, layer),
+            None => return Err((ERROR_INVALID_ARGS, String::from("Expected the name of the layer."))),
+        },
+        "GetLayer" => return Ok(vec![Value::String(String::from(gesture_detector.layers.current()))]),
+        "ListBindings" => {
+            return Ok(vec![Value::Array(String::from("(ssuss)"), config.actions.iter().map(binding).collect())]);
+        },
+        "ReloadConfig" => reload_config(gesture_detector),
+        _ => return Err((ERROR_UNKNOWN_METHOD, format!("Unknown method {}.", member))),
+    }
+
+    Ok(vec![])
+}
+
+impl Service {
+    // Connects to the session bus and takes the name of the service.
+    pub fn new() -> Result<Service, String> {
+        let mut connection = Connection::session()?;
+        let reply = connection.call("org.freedesktop.DBus",
+                                    "/org/freedesktop/DBus",
+                                    "org.freedesktop.DBus",
+                                    "RequestName",
+                                    vec![Value::String(String::from(NAME)), Value::UInt32(DO_NOT_QUEUE)])?;
+        if reply.first().and_then(Value::as_u32) != Some(PRIMARY_OWNER) {
+            return Err(format!("{} is already taken, is edgy already running?", NAME));
+        }
+
+        println!("Registered {} on the session bus.", NAME);
+        Ok(Service {
+            connection: Some(connection),
+        })
+    }
+
+    fn disconnect(&mut self, error: &str) {
+        println!("Lost connection to the session bus: {}", error);
+        self.connection = None;
+    }
+
+    // File descriptor to wait on for incoming calls.
+    pub fn fd(&self) -> Option<RawFd> {
+        self.connection.as_ref().map(Connection::fd)
+    }
+
+    pub fn emit_gesture(&mut self, gesture: &Gesture) {
+        let result = match self.connection {
+            Some(ref mut connection) => connection.emit_signal(PATH, INTERFACE, "Gesture", vec![
+                Value::String(gesture.side.to_string()),
+                Value::String(gesture.direction.to_string()),
+                Value::UInt32(gesture.num_fingers),
+            ]),
+            None => return,
+        };
+        if let Err(error) = result {
+            self.disconnect(&error);
+        }
+    }
+
+    // Handles the method calls received so far, without waiting.
+    pub fn dispatch(&mut self, gesture_detector: &mut GestureDetector) {
+        loop {
+            let message = match self.connection.as_mut().map(Connection::poll_message) {
+                Some(Ok(Some(message))) => message,
+                Some(Ok(None)) | None => return,
+                Some(Err(error)) => {
+                    self.disconnect(&error);
+                    return;
+                },
+            };
+
+            if message.message_type != dbus::METHOD_CALL {
+                continue;
+            }
+
+            let reply = match handle_call(gesture_detector, &message) {
+                Ok(body) => Message::method_return(&message, body),
+                Err((error_name, text)) => Message::error_reply(&message, error_name, &text),
+            };
+            if !message.expects_reply() {
+                continue;
+            }
+            if let Err(error) = self.connection.as_mut().unwrap().send(reply) {
+                self.disconnect(&error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Config;
+
+    fn call(gesture_detector: &mut GestureDetector, member: &str, body: Vec<Value>) -> CallResult {
+        handle_call(gesture_detector, &Message::method_call(NAME, PATH, INTERFACE, member, body))
+    }
+
+    #[test]
+    fn reload_config_goes_back_to_the_initial_layer() {
+        let config = Config::fake(&[]);
+        let mut on_accept_touch = |_, _, _| {};
+        let mut on_reject_touch = |_, _, _| {};
+        let mut on_gesture = |_: &mut GestureDetector, _: &Gesture| {};
+        let mut gesture_detector = GestureDetector::new(&config, &mut on_accept_touch, &mut on_reject_touch,
+                                                        &mut on_gesture);
+
+        call(&mut gesture_detector, "SwitchLayer", vec![Value::String(String::from("presentation"))]).unwrap();
+        gesture_detector.layers.push(&config, "unlocking");
+        assert_eq!(gesture_detector.layers.current(), "unlocking");
+
+        assert_eq!(call(&mut gesture_detector, "ReloadConfig", vec![]), Ok(vec![]));
+        assert_eq!(gesture_detector.layers.current(), "normal");
+        assert!(!gesture_detector.layers.is_temporary());
+        assert_eq!(*config.device_ids.borrow(), vec![11]);
+    }
+
+    #[test]
+    fn reload_config_is_introspected() {
+        let config = Config::fake(&[]);
+        let mut on_accept_touch = |_, _, _| {};
+        let mut on_reject_touch = |_, _, _| {};
+        let mut on_gesture = |_: &mut GestureDetector, _: &Gesture| {};
+        let mut gesture_detector = GestureDetector::new(&config, &mut on_accept_touch, &mut on_reject_touch,
+                                                        &mut on_gesture);
+
+        let message = Message::method_call(NAME, PATH, INTROSPECTABLE_INTERFACE, "Introspect", vec![]);
+        match handle_call(&mut gesture_detector, &message) {
+            Ok(ref values) => assert!(values[0].as_str().unwrap().contains("<method name=\"ReloadConfig\"/>")),
+            Err(error) => panic!("Introspect failed: {:?}", error),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Describes the XInput devices for `edgy list-devices`, for picking a device
+//! for -d and finding out why a touchscreen is not used.
+
+use std::ffi::CStr;
+use std::fmt::Write;
+use x11::{xlib, xinput2};
+
+use xconn::XConnection;
+
+fn device_use(device: &xinput2::XIDeviceInfo) -> String {
+    match device._use {
+        xinput2::XIMasterPointer => String::from("master pointer"),
+        xinput2::XIMasterKeyboard => String::from("master keyboard"),
+        xinput2::XISlavePointer => format!("slave pointer, attached to #{}", device.attachment),
+        xinput2::XISlaveKeyboard => format!("slave keyboard, attached to #{}", device.attachment),
+        xinput2::XIFloatingSlave => String::from("floating slave"),
+        _ => String::from("unknown"),
+    }
+}
+
+fn atom_name(x11: &XConnection, atom: xlib::Atom) -> String {
+    if atom == 0 {
+        return String::from("unlabeled");
+    }
+    unsafe {
+        let name = xlib::XGetAtomName(x11.display(), atom);
+        if name.is_null() {
+            return String::from("unlabeled");
+        }
+        let result = CStr::from_ptr(name).to_string_lossy().into_owned();
+        xlib::XFree(name as *mut _);
+        result
+    }
+}
+
+// Returns the part of the screen the device is mapped to, from its coordinate
+// transformation matrix.
+fn mapped_output(x11: &XConnection, device_id: i32) -> String {
+    let matrix: Vec<f32> = match x11.get_xinput_property(device_id, "Coordinate Transformation Matrix") {
+        Some(ref items) if items.len() == 9 => items.iter().map(|&item| f32::from_bits(item)).collect(),
+        _ => return String::from("unknown"),
+    };
+    if matrix == [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0] {
+        return String::from("whole screen");
+    }
+
+    // The matrix maps the device's unit square to the screen's.
+    let (width, height) = match x11.get_root_window_size() {
+        Ok(size) => size,
+        Err(_) => return String::from("unknown"),
+    };
+    let x = (matrix[0] * 0.5 + matrix[1] * 0.5 + matrix[2]) as f64 * width as f64;
+    let y = (matrix[3] * 0.5 + matrix[4] * 0.5 + matrix[5]) as f64 * height as f64;
+    x11.get_output_name_at(x, y).unwrap_or_else(|| String::from("none"))
+}
+
+/// Returns a description of each XInput device: its id, name and use, its
+/// touch class and axes, and the output a touchscreen is mapped to.
+pub fn describe_xinput_devices(x11: &XConnection) -> String {
+    let touchscreens: Vec<i32> = x11.list_xinput_devices().iter()
+        .filter(|device| device.is_touchscreen)
+        .map(|device| device.id)
+        .collect();
+
+    let mut result = String::new();
+    x11.for_each_xinput_device(&mut |device: *mut xinput2::XIDeviceInfo| {
+        let device = unsafe { &*device };
+        let name = unsafe { CStr::from_ptr(device.name) }.to_string_lossy();
+
+        let _ = writeln!(result, "#{} {}", device.deviceid, name);
+        let _ = writeln!(result, "    use: {}{}", device_use(device),
+                         if device.enabled == 0 { ", disabled" } else { "" });
+        if let Some(ids) = x11.get_xinput_property(device.deviceid, "Device Product ID") {
+            if ids.len() == 2 {
+                let _ = writeln!(result, "    vendor:product: {:04x}:{:04x}", ids[0], ids[1]);
+            }
+        }
+
+        let mut has_touch = false;
+        for i in 0..device.num_classes as usize {
+            let class = unsafe { *device.classes.add(i) };
+            match unsafe { (*class)._type } {
+                xinput2::XITouchClass => {
+                    let touch = unsafe { &*(class as *const xinput2::XITouchClassInfo) };
+                    let mode = if touch.mode == xinput2::XIDirectTouch { "direct" } else { "dependent" };
+                    let _ = writeln!(result, "    touch: {}, up to {} touches", mode, touch.num_touches);
+                    has_touch = true;
+                },
+                xinput2::XIValuatorClass => {
+                    let valuator = unsafe { &*(class as *const xinput2::XIValuatorClassInfo) };
+                    let _ = writeln!(result, "    axis {}: {}, {} to {}{}", valuator.number,
+                                     atom_name(x11, valuator.label), valuator.min, valuator.max,
+                                     if valuator.mode == xinput2::XIModeRelative { ", relative" } else { "" });
+                },
+                _ => {},
+            }
+        }
+
+        if touchscreens.contains(&device.deviceid) {
+            let _ = writeln!(result, "    output: {}", mapped_output(x11, device.deviceid));
+            let _ = writeln!(result, "    used by default as a touchscreen");
+        }
+        // Master devices copy the classes of the device last used.
+        else if has_touch && device._use != xinput2::XIMasterPointer {
+            let _ = writeln!(result, "    touchpad, swipes over the edges of its surface with -d {}", device.deviceid);
+        }
+    });
+    result
+}
@@ -0,0 +1,210 @@
+//! Selecting input devices on the command line. A device is given by its id,
+//! e.g. 11, its vendor and product id, e.g. 04f3:2234, a regular expression
+//! matching its name, e.g. /ELAN.*Touch/, or a part of its name. A name matches
+//! only the devices with exactly that name if there are any. Digits are always
+//! taken as an id, a device whose name is only digits is selected with a
+//! pattern such as /^1234$/.
+
+use std::fmt;
+
+use regex::Regex;
+
+/// What is known about an input device for selecting it.
+pub struct DeviceInfo {
+    /// XInput device id.
+    pub id: i32,
+    /// Name of the device, as shown by xinput list.
+    pub name: String,
+    /// (vendor, product), if the device reports them.
+    pub vendor_product: Option<(u16, u16)>,
+    /// Whether the device is a touchscreen, i.e. touches are at points on the
+    /// screen rather than relative to the pointer.
+    pub is_touchscreen: bool,
+}
+
+/// A device given on the command line.
+pub enum DeviceSpec {
+    /// The device with the id, e.g. 11.
+    Id(i32),
+    /// The devices with the USB vendor and product id, e.g. 04f3:2234.
+    VendorProduct(u16, u16),
+    /// The devices whose names match, e.g. /ELAN.*Touch/.
+    Pattern(Regex),
+    /// The devices with exactly the name, or containing it if none has it.
+    Name(String),
+}
+
+fn parse_vendor_product(spec: &str) -> Option<(u16, u16)> {
+    let mut parts = spec.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(vendor), Some(product)) if vendor.len() == 4 && product.len() == 4 => {
+            match (u16::from_str_radix(vendor, 16), u16::from_str_radix(product, 16)) {
+                (Ok(vendor), Ok(product)) => Some((vendor, product)),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+impl DeviceSpec {
+    /// Parses a device as given with -d, see the module documentation.
+    pub fn parse(spec: &str) -> Result<DeviceSpec, String> {
+        if !spec.is_empty() && spec.chars().all(|c| c.is_ascii_digit()) {
+            return spec.parse().map(DeviceSpec::Id).map_err(|e| format!("Invalid device id {}: {}", spec, e));
+        }
+
+        if let Some((vendor, product)) = parse_vendor_product(spec) {
+            return Ok(DeviceSpec::VendorProduct(vendor, product));
+        }
+
+        if spec.len() >= 2 && spec.starts_with('/') && spec.ends_with('/') {
+            return Regex::new(&spec[1..spec.len() - 1])
+                .map(DeviceSpec::Pattern)
+                .map_err(|e| format!("Invalid device pattern {}: {}", spec, e));
+        }
+
+        Ok(DeviceSpec::Name(String::from(spec)))
+    }
+
+    /// Returns the devices matching the spec.
+    pub fn select<'a>(&self, devices: &'a [DeviceInfo]) -> Vec<&'a DeviceInfo> {
+        let has_exact_name = match *self {
+            DeviceSpec::Name(ref name) => devices.iter().any(|device| device.name == *name),
+            _ => false,
+        };
+
+        devices.iter()
+            .filter(|device| match *self {
+                DeviceSpec::Id(id) => device.id == id,
+                DeviceSpec::VendorProduct(vendor, product) => device.vendor_product == Some((vendor, product)),
+                DeviceSpec::Pattern(ref pattern) => pattern.is_match(&device.name),
+                DeviceSpec::Name(ref name) if has_exact_name => device.name == *name,
+                DeviceSpec::Name(ref name) => device.name.contains(name.as_str()),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for DeviceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeviceSpec::Id(id) => write!(f, "#{}", id),
+            DeviceSpec::VendorProduct(vendor, product) => write!(f, "{:04x}:{:04x}", vendor, product),
+            DeviceSpec::Pattern(ref pattern) => write!(f, "/{}/", pattern),
+            DeviceSpec::Name(ref name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Returns the id and name of the devices matching any of the specs. Specs
+/// that match no device are warned about.
+pub fn select_devices(specs: &[DeviceSpec], devices: &[DeviceInfo]) -> Vec<(i32, String)> {
+    let mut result: Vec<(i32, String)> = Vec::new();
+    for spec in specs {
+        let selected = spec.select(devices);
+        if selected.is_empty() {
+            println!("Warning: no device matches {}.", spec);
+        }
+        for device in selected {
+            if !result.iter().any(|&(id, _)| id == device.id) {
+                result.push((device.id, device.name.clone()));
+            }
+        }
+    }
+    result
+}
+
+/// Returns the id and name of the touchscreens.
+pub fn find_touchscreens(devices: &[DeviceInfo]) -> Vec<(i32, String)> {
+    devices.iter()
+        .filter(|device| device.is_touchscreen)
+        .map(|device| (device.id, device.name.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(id: i32, name: &str, vendor_product: Option<(u16, u16)>) -> DeviceInfo {
+        DeviceInfo {
+            id: id,
+            name: String::from(name),
+            vendor_product: vendor_product,
+            is_touchscreen: false,
+        }
+    }
+
+    fn devices() -> Vec<DeviceInfo> {
+        vec![device(11, "ELAN Touchscreen", Some((0x04f3, 0x2234))),
+             device(12, "ELAN Touchscreen Pen", Some((0x04f3, 0x2234))),
+             device(13, "Synaptics Touchpad", None),
+             device(14, "11", None)]
+    }
+
+    fn selected_ids(spec: &str) -> Vec<i32> {
+        DeviceSpec::parse(spec).unwrap().select(&devices()).iter().map(|device| device.id).collect()
+    }
+
+    #[test]
+    fn digits_are_an_id() {
+        match DeviceSpec::parse("11") {
+            Ok(DeviceSpec::Id(11)) => {},
+            _ => panic!("11 is not parsed as an id"),
+        }
+        assert_eq!(selected_ids("11"), vec![11]);
+        assert_eq!(selected_ids("15"), Vec::<i32>::new());
+        assert!(DeviceSpec::parse("99999999999").is_err());
+    }
+
+    #[test]
+    fn vendor_product() {
+        match DeviceSpec::parse("04f3:2234") {
+            Ok(DeviceSpec::VendorProduct(0x04f3, 0x2234)) => {},
+            _ => panic!("04f3:2234 is not parsed as a vendor and product id"),
+        }
+        assert_eq!(selected_ids("04f3:2234"), vec![11, 12]);
+        assert_eq!(selected_ids("04F3:2234"), vec![11, 12]);
+        // Not 4 hex digits each, so names.
+        for spec in &["4f3:2234", "04f3:22345", "04g3:2234"] {
+            match DeviceSpec::parse(spec) {
+                Ok(DeviceSpec::Name(ref name)) if name == spec => {},
+                _ => panic!("{} is not parsed as a name", spec),
+            }
+        }
+    }
+
+    #[test]
+    fn pattern() {
+        assert_eq!(selected_ids("/ELAN.*Pen/"), vec![12]);
+        assert_eq!(selected_ids("/^ELAN/"), vec![11, 12]);
+        // The way to select a device named with digits.
+        assert_eq!(selected_ids("/^11$/"), vec![14]);
+        assert!(DeviceSpec::parse("/(/").is_err());
+        // A single slash is a name.
+        match DeviceSpec::parse("/") {
+            Ok(DeviceSpec::Name(ref name)) if name == "/" => {},
+            _ => panic!("/ is not parsed as a name"),
+        }
+    }
+
+    #[test]
+    fn exact_name_before_substring() {
+        assert_eq!(selected_ids("ELAN Touchscreen"), vec![11]);
+        assert_eq!(selected_ids("ELAN"), vec![11, 12]);
+        assert_eq!(selected_ids("Touch"), vec![11, 12, 13]);
+        assert_eq!(selected_ids("Mouse"), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn select_devices_merges_specs() {
+        let specs: Vec<DeviceSpec> = ["ELAN", "11", "Touchpad", "Mouse"].iter()
+            .map(|spec| DeviceSpec::parse(spec).unwrap())
+            .collect();
+        assert_eq!(select_devices(&specs, &devices()),
+                   vec![(11, String::from("ELAN Touchscreen")),
+                        (12, String::from("ELAN Touchscreen Pen")),
+                        (13, String::from("Synaptics Touchpad"))]);
+    }
+}
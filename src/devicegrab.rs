@@ -1,74 +1,253 @@
-use xconn::*;
-use std::os::raw::c_uchar;
+//! Functions to disable / enable touch input of devices for other applications.
+//!
+//! A grabbed device only sends its events to edgy, so gestures can still be
+//! detected, e.g. to enable it again. A device disabled with the "Device
+//! Enabled" property does not send events to anyone, including edgy.
+
+use xconn::XConnection;
+use config::{Config, TouchDisableMethod};
+use device_spec::{self, find_touchscreens};
+use events;
+use json::Json;
+use notifications;
+use std::os::raw::{c_int, c_uchar};
+use std::ptr::null_mut;
 use x11::{xlib, xinput2};
+use evdev;
 use std::vec::Vec;
 
-// Functions to grab / ungrab the whole device, to prevent events from going thorugh.
-pub static mut is_grabbed:bool = false;
+// The XInput methods are only used with an X connection, without one touch
+// input is left alone.
+fn connection(config: &Config) -> Option<&XConnection> {
+    config.x11.as_deref()
+}
 
-pub fn grab_devices(device_ids:&Vec<i32>) {
-    unsafe {
-        if is_grabbed {
-            return;
-        }
-        is_grabbed = true;
+fn grab_device(x11: &XConnection, device_id: i32) {
+    let mut mask: [c_uchar; 4] = [0; 4];
+
+    let mut input_event_mask = xinput2::XIEventMask {
+        deviceid: device_id,
+        mask_len: mask.len() as i32,
+        mask: mask.as_mut_ptr(),
+    };
+
+    for &event in &[ xinput2::XI_TouchBegin,
+                     xinput2::XI_TouchUpdate,
+                     xinput2::XI_TouchEnd,
+
+                     xinput2::XI_ButtonPress,
+                     xinput2::XI_ButtonRelease,
+                     xinput2::XI_Motion, ]
+    {
+        xinput2::XISetMask(&mut mask, event);
     }
 
-    for &device_id in device_ids {
-        let mut mask: [c_uchar; 4] = [0; 4];
+    println!("Grabbing device #{}", device_id);
+    // Asynchronous, so that the events keep flowing to edgy without having to
+    // allow them one by one.
+    unsafe { xinput2::XIGrabDevice(x11.display(),
+                                device_id,
+                                x11.root_window(),
+                                xlib::CurrentTime,
+                                /*cursor=*/0,
+                                xlib::GrabModeAsync,
+                                xlib::GrabModeAsync,
+                                /*owner_events*/xlib::False,
+                                &mut input_event_mask); }
+    unsafe { xlib::XSync(x11.display(), xlib::False); }
+
+    x11.set_grabbed(device_id, true);
+}
 
-        let mut input_event_mask = xinput2::XIEventMask {
-            deviceid: 2, //xinput2::XIAllDevices,
-            mask_len: mask.len() as i32,
-            mask: mask.as_mut_ptr(),
-        };
+fn ungrab_device(x11: &XConnection, device_id: i32) {
+    println!("Ungrabbing device #{}.", device_id);
+    unsafe { xinput2::XIUngrabDevice(x11.display(), device_id, xlib::CurrentTime); }
+    unsafe { xlib::XSync(x11.display(), xlib::False); }
 
-        for &event in &[ xinput2::XI_TouchBegin,
-                         xinput2::XI_TouchUpdate,
-                         xinput2::XI_TouchEnd,
+    x11.set_grabbed(device_id, false);
+}
+
+fn is_device_enabled(x11: &XConnection, device_id: i32) -> bool {
+    let mut actual_type = 0;
+    let mut actual_format = 0;
+    let mut num_items = 0;
+    let mut bytes_after = 0;
+    let mut data: *mut c_uchar = null_mut();
 
-                         xinput2::XI_ButtonPress,
-                         xinput2::XI_ButtonRelease,
-                         xinput2::XI_Motion, ]
+    unsafe {
+        if xinput2::XIGetProperty(x11.display(),
+                                  device_id,
+                                  x11.device_enabled_atom(),
+                                  0,
+                                  1,
+                                  xlib::False,
+                                  xlib::XA_INTEGER,
+                                  &mut actual_type,
+                                  &mut actual_format,
+                                  &mut num_items,
+                                  &mut bytes_after,
+                                  &mut data) != xlib::Success as c_int || data.is_null()
         {
-            xinput2::XISetMask(&mut mask, event);
+            return true;
         }
 
-        println!("Grabbing device #{}", device_id);
-        unsafe { xinput2::XIGrabDevice(display.unwrap(), 
-                                    device_id, 
-                                    root_window,
-                                    xlib::CurrentTime,
-                                    /*cursor=*/0,
-                                    xlib::GrabModeSync,
-                                    xlib::GrabModeAsync,
-                                    /*owner_events*/xlib::False, 
-                                    &mut input_event_mask); }
-        unsafe { xlib::XSync(display.unwrap(), xlib::True); }
+        let enabled = num_items == 0 || *data != 0;
+        xlib::XFree(data as *mut _);
+        enabled
     }
 }
 
-pub fn ungrab_devices(device_ids:&Vec<i32>) {
-    unsafe {
-        if !is_grabbed {
-            return;
+fn set_device_enabled(x11: &XConnection, device_id: i32, enabled: bool) {
+    println!("{} device #{}.", if enabled { "Enabling" } else { "Disabling" }, device_id);
+    x11.set_device_enabled(device_id, enabled);
+}
+
+/// Returns whether touch input of the device is disabled for other
+/// applications.
+pub fn is_touch_disabled(config: &Config, device_id: i32) -> bool {
+    match (&config.touch_disable_method, connection(config)) {
+        (TouchDisableMethod::Grab, Some(x11)) => x11.is_grabbed(device_id),
+        (TouchDisableMethod::DeviceEnabledProperty, Some(x11)) => !is_device_enabled(x11, device_id),
+        (TouchDisableMethod::EvdevGrab, _) => evdev::is_grabbed(device_id),
+        (_, None) => false,
+    }
+}
+
+fn report_changed(config: &Config, event: &str, summary: &str, device_ids: &[i32]) {
+    if device_ids.is_empty() {
+        return;
+    }
+
+    events::emit(event, vec![("devices", Json::Array(device_ids.iter().map(|&id| Json::from(id)).collect()))]);
+
+    if !config.notify_touch_changes {
+        return;
+    }
+
+    let device_names = config.device_names.borrow();
+    let names: Vec<&str> = device_ids.iter()
+        .filter_map(|device_id| device_names.get(device_id))
+        .map(|name| name.as_str())
+        .collect();
+    notifications::notify(summary, &names.join(", "));
+}
+
+/// Disables touch input of the devices for other applications, with the
+/// method of the config.
+pub fn disable_touch(config: &Config, device_ids: &[i32]) {
+    let mut changed = Vec::new();
+    for &device_id in device_ids {
+        if is_touch_disabled(config, device_id) {
+            continue;
+        }
+
+        match (&config.touch_disable_method, connection(config)) {
+            (TouchDisableMethod::Grab, Some(x11)) => grab_device(x11, device_id),
+            (TouchDisableMethod::DeviceEnabledProperty, Some(x11)) => set_device_enabled(x11, device_id, false),
+            (TouchDisableMethod::EvdevGrab, _) => evdev::grab(device_id),
+            (_, None) => continue,
         }
-        is_grabbed = false;
+        changed.push(device_id);
     }
 
+    report_changed(config, "touch_disabled", "Touchscreen disabled", &changed);
+}
+
+/// Enables touch input of the devices again.
+pub fn enable_touch(config: &Config, device_ids: &[i32]) {
+    let mut changed = Vec::new();
     for &device_id in device_ids {
-        println!("Ungrabbing device #{}.", device_id);
-        unsafe { xinput2::XIUngrabDevice(display.unwrap(), device_id, xlib::CurrentTime); } 
+        if !is_touch_disabled(config, device_id) {
+            continue;
+        }
+
+        match (&config.touch_disable_method, connection(config)) {
+            (TouchDisableMethod::Grab, Some(x11)) => ungrab_device(x11, device_id),
+            (TouchDisableMethod::DeviceEnabledProperty, Some(x11)) => set_device_enabled(x11, device_id, true),
+            (TouchDisableMethod::EvdevGrab, _) => evdev::ungrab(device_id),
+            (_, None) => continue,
+        }
+        changed.push(device_id);
     }
 
-    unsafe { xlib::XSync(display.unwrap(), xlib::True); }
+    report_changed(config, "touch_enabled", "Touchscreen enabled", &changed);
 }
 
-pub fn toggle_grab_devices(device_ids:&Vec<i32>) {
-    if unsafe { is_grabbed } {
-        ungrab_devices(device_ids);
+/// Enables the devices if all of them are disabled, otherwise disables them.
+pub fn toggle_touch(config: &Config, device_ids: &[i32]) {
+    if device_ids.iter().all(|&device_id| is_touch_disabled(config, device_id)) {
+        enable_touch(config, device_ids);
     }
     else {
-        grab_devices(device_ids);
+        disable_touch(config, device_ids);
+    }
+}
+
+/// Returns the configured devices with the name, or all configured devices if
+/// no name is given.
+pub fn select_devices(config: &Config, name: Option<&str>) -> Vec<i32> {
+    match name {
+        None => config.device_ids.borrow().clone(),
+        Some(name) => {
+            let device_ids: Vec<i32> = config.device_ids.borrow().iter()
+                .cloned()
+                .filter(|device_id| config.device_names.borrow().get(device_id).map(|n| n.as_str()) == Some(name))
+                .collect();
+            if device_ids.is_empty() {
+                println!("No device named {} is in use.", name);
+            }
+            device_ids
+        },
     }
 }
+
+/// Selects the XInput devices in use again after devices were added or
+/// removed. A grabbed or disabled device that is plugged back in is disabled
+/// again. Returns the devices that were added.
+pub fn update_devices(config: &Config) -> Vec<i32> {
+    let x11 = match connection(config) {
+        Some(x11) => x11,
+        None => return Vec::new(),
+    };
+    let available_devices = x11.list_xinput_devices();
+    let devices = if config.device_specs.is_empty() {
+        find_touchscreens(&available_devices)
+    }
+    else {
+        device_spec::select_devices(&config.device_specs, &available_devices)
+    };
+
+    let old_ids = config.device_ids.borrow().clone();
+    for &device_id in &old_ids {
+        if devices.iter().any(|&(id, _)| id == device_id) {
+            continue;
+        }
+
+        let name = config.device_names.borrow_mut().remove(&device_id).unwrap_or_default();
+        println!("Device {} (#{}) was removed.", name, device_id);
+        // The grab ended with the device, its id may be given to another one.
+        x11.device_removed(device_id, &name);
+    }
+
+    let mut added = Vec::new();
+    for (device_id, name) in devices.iter().cloned() {
+        if old_ids.contains(&device_id) {
+            continue;
+        }
+
+        println!("Using device {} (#{}).", name, device_id);
+        config.device_names.borrow_mut().insert(device_id, name);
+        added.push(device_id);
+    }
+    *config.device_ids.borrow_mut() = devices.iter().map(|&(id, _)| id).collect();
+
+    // A device plugged back in comes back enabled, whether it was grabbed or
+    // disabled with the property.
+    let disable: Vec<i32> = added.iter()
+        .cloned()
+        .filter(|device_id| x11.take_removed_disabled(&config.device_names.borrow()[device_id]))
+        .collect();
+    disable_touch(config, &disable);
+
+    added
+}
@@ -0,0 +1,240 @@
+// Reading Linux input devices, /dev/input/event*, directly. Used by the evdev
+// backend, which works without X.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::mem::{size_of, zeroed};
+use std::os::raw::{c_char, c_int, c_ulong};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::slice;
+
+use libc;
+
+// From linux/input-event-codes.h.
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_ABS: u16 = 0x03;
+pub const SYN_REPORT: u16 = 0;
+pub const SYN_DROPPED: u16 = 3;
+pub const BTN_TOUCH: u16 = 0x14a;
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
+pub const ABS_MT_SLOT: u16 = 0x2f;
+pub const ABS_MT_POSITION_X: u16 = 0x35;
+pub const ABS_MT_POSITION_Y: u16 = 0x36;
+pub const ABS_MT_TRACKING_ID: u16 = 0x39;
+pub const ABS_CNT: u16 = 0x40;
+pub const INPUT_PROP_DIRECT: u16 = 0x01;
+pub const INPUT_PROP_CNT: u16 = 0x20;
+
+// From asm-generic/ioctl.h.
+pub const IOC_NONE: c_ulong = 0;
+pub const IOC_WRITE: c_ulong = 1;
+pub const IOC_READ: c_ulong = 2;
+
+pub fn ioc(direction: c_ulong, kind: u8, number: c_ulong, size: usize) -> c_ulong {
+    direction << 30 | (size as c_ulong) << 16 | (kind as c_ulong) << 8 | number
+}
+
+fn eviocgname(len: usize) -> c_ulong { ioc(IOC_READ, b'E', 0x06, len) }
+fn eviocgbit(event_type: u16, len: usize) -> c_ulong { ioc(IOC_READ, b'E', 0x20 + event_type as c_ulong, len) }
+fn eviocgabs(axis: u16) -> c_ulong { ioc(IOC_READ, b'E', 0x40 + axis as c_ulong, size_of::<libc::input_absinfo>()) }
+fn eviocgprop(len: usize) -> c_ulong { ioc(IOC_READ, b'E', 0x09, len) }
+fn eviocgid() -> c_ulong { ioc(IOC_READ, b'E', 0x02, size_of::<libc::input_id>()) }
+fn eviocgrab() -> c_ulong { ioc(IOC_WRITE, b'E', 0x90, size_of::<c_int>()) }
+
+struct OpenDevice {
+    fd: RawFd,
+    // Grabbed for as long as it is open, grabbing only stops passing touches
+    // through.
+    is_exclusive: bool,
+}
+
+thread_local! {
+    // Devices opened by a backend, for grabbing them when touch is disabled.
+    static DEVICES: RefCell<HashMap<i32, OpenDevice>> = RefCell::new(HashMap::new());
+    // Devices currently grabbed.
+    static GRABBED_DEVICES: RefCell<Vec<i32>> = const { RefCell::new(Vec::new()) };
+}
+
+/// An opened /dev/input/eventN. Its id is N.
+pub struct Device {
+    pub id: i32,
+    pub name: String,
+    pub input_id: libc::input_id,
+    file: File,
+}
+
+/// Returns N of /dev/input/eventN.
+pub fn device_id(path: &Path) -> Option<i32> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix("event"))
+        .and_then(|number| number.parse().ok())
+}
+
+impl Device {
+    pub fn open(path: &Path) -> Result<Device, String> {
+        let id = device_id(path).ok_or_else(|| format!("{} is not an event device.", path.display()))?;
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+            .open(path)
+            .map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+
+        let mut name = [0 as c_char; 256];
+        let mut input_id: libc::input_id = unsafe { zeroed() };
+        unsafe {
+            if libc::ioctl(file.as_raw_fd(), eviocgname(name.len() - 1) as libc::Ioctl, name.as_mut_ptr()) < 0 ||
+               libc::ioctl(file.as_raw_fd(), eviocgid() as libc::Ioctl, &mut input_id) < 0
+            {
+                return Err(format!("{} is not an event device.", path.display()));
+            }
+        }
+
+        Ok(Device {
+            id: id,
+            name: unsafe { CStr::from_ptr(name.as_ptr()) }.to_string_lossy().into_owned(),
+            input_id: input_id,
+            file: file,
+        })
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    /// Whether the device reports the absolute axis.
+    pub fn has_axis(&self, axis: u16) -> bool {
+        let mut bits = [0u8; (ABS_CNT as usize).div_ceil(8)];
+        let result = unsafe {
+            libc::ioctl(self.fd(), eviocgbit(EV_ABS, bits.len()) as libc::Ioctl, bits.as_mut_ptr())
+        };
+        result >= 0 && bits[axis as usize / 8] & (1 << (axis % 8)) != 0
+    }
+
+    /// Whether the device has the property, e.g. INPUT_PROP_DIRECT for
+    /// touchscreens.
+    pub fn has_property(&self, property: u16) -> bool {
+        let mut bits = [0u8; (INPUT_PROP_CNT as usize).div_ceil(8)];
+        let result = unsafe {
+            libc::ioctl(self.fd(), eviocgprop(bits.len()) as libc::Ioctl, bits.as_mut_ptr())
+        };
+        result >= 0 && bits[property as usize / 8] & (1 << (property % 8)) != 0
+    }
+
+    pub fn axis_info(&self, axis: u16) -> Option<libc::input_absinfo> {
+        let mut info: libc::input_absinfo = unsafe { zeroed() };
+        if unsafe { libc::ioctl(self.fd(), eviocgabs(axis) as libc::Ioctl, &mut info) } < 0 {
+            return None;
+        }
+        Some(info)
+    }
+
+    /// Reads the events available without waiting.
+    pub fn read_events(&mut self) -> io::Result<Vec<libc::input_event>> {
+        let mut events = Vec::new();
+        let mut event: libc::input_event = unsafe { zeroed() };
+        loop {
+            let buffer = unsafe {
+                slice::from_raw_parts_mut(&mut event as *mut _ as *mut u8, size_of::<libc::input_event>())
+            };
+            match self.file.read(buffer) {
+                Ok(n) if n == buffer.len() => events.push(event),
+                Ok(_) => return Ok(events),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(events),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Makes the device available to grab() and ungrab(), e.g. for disabling
+    /// touch input.
+    pub fn register(&self) {
+        DEVICES.with(|devices| devices.borrow_mut().insert(self.id, OpenDevice { fd: self.fd(), is_exclusive: false }));
+    }
+
+    /// Grabs the device for as long as it is open. grab() and ungrab() then
+    /// only change is_grabbed(), for the backend to stop or resume passing
+    /// touches through.
+    pub fn grab_exclusively(&self) -> io::Result<()> {
+        if unsafe { libc::ioctl(self.fd(), eviocgrab() as libc::Ioctl, 1 as c_int) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        DEVICES.with(|devices| devices.borrow_mut().insert(self.id, OpenDevice { fd: self.fd(), is_exclusive: true }));
+        Ok(())
+    }
+
+    /// Releases the grab of grab_exclusively(). The device is then grabbed and
+    /// ungrabbed like a registered one.
+    pub fn ungrab_exclusively(&self) -> io::Result<()> {
+        if unsafe { libc::ioctl(self.fd(), eviocgrab() as libc::Ioctl, 0 as c_int) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        GRABBED_DEVICES.with(|grabbed| grabbed.borrow_mut().retain(|&id| id != self.id));
+        self.register();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Device {
+    /// A device that reports nothing, for testing the backend.
+    pub fn fake(id: i32, name: &str) -> Device {
+        Device {
+            id: id,
+            name: String::from(name),
+            input_id: unsafe { zeroed() },
+            file: File::open("/dev/null").unwrap(),
+        }
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        DEVICES.with(|devices| devices.borrow_mut().remove(&self.id));
+        GRABBED_DEVICES.with(|grabbed| grabbed.borrow_mut().retain(|&id| id != self.id));
+    }
+}
+
+fn set_grab(device_id: i32, grab: bool) -> bool {
+    let fd = match DEVICES.with(|devices| devices.borrow().get(&device_id).map(|device| (device.fd, device.is_exclusive))) {
+        Some((_, true)) => return true,
+        Some((fd, false)) => fd,
+        None => {
+            println!("Device #{} is not open.", device_id);
+            return false;
+        },
+    };
+    if unsafe { libc::ioctl(fd, eviocgrab() as libc::Ioctl, grab as c_int) } < 0 {
+        println!("Could not {} device #{}: {}", if grab { "grab" } else { "ungrab" }, device_id,
+                 io::Error::last_os_error());
+        return false;
+    }
+    true
+}
+
+/// Grabs the device, so that only edgy receives its events. The device must
+/// have been opened by a backend.
+pub fn grab(device_id: i32) {
+    println!("Grabbing device #{}", device_id);
+    if set_grab(device_id, true) {
+        GRABBED_DEVICES.with(|grabbed| grabbed.borrow_mut().push(device_id));
+    }
+}
+
+pub fn ungrab(device_id: i32) {
+    println!("Ungrabbing device #{}.", device_id);
+    if set_grab(device_id, false) {
+        GRABBED_DEVICES.with(|grabbed| grabbed.borrow_mut().retain(|&id| id != device_id));
+    }
+}
+
+pub fn is_grabbed(device_id: i32) -> bool {
+    GRABBED_DEVICES.with(|grabbed| grabbed.borrow().contains(&device_id))
+}
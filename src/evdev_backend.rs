@@ -0,0 +1,700 @@
+//! Touches read directly from touchscreens, /dev/input/event*, using the
+//! multi-touch protocol B (slots). Works without X, e.g. under Wayland.
+//!
+//! Reading a device does not take its touches from other applications, so
+//! accepting and rejecting touches has no effect. Touch input is disabled by
+//! grabbing the device with EVIOCGRAB.
+//!
+//! With pass-through, the devices are grabbed from the start and each gets a
+//! virtual clone through uinput. Touches are held back until the gesture
+//! detector decides on them, like with XInput touch grabs: rejected touches are
+//! replayed on the clone and followed from there on, accepted touches are
+//! dropped. Disabling touch input then only stops the passing through.
+
+use std::collections::{HashMap, VecDeque};
+use std::os::raw::c_int;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use libc;
+
+use backend::{poll, InputBackend, TouchEvent};
+use evdev;
+use evdev::*;
+use uinput::VirtualTouchscreen;
+
+#[derive(Copy, Clone, Default)]
+struct Slot {
+    // None if nothing touches the slot.
+    touch_id: Option<i32>,
+    x: i32,
+    y: i32,
+    began: bool,
+    moved: bool,
+    // Touch that ended in the slot since the last report.
+    ended: Option<i32>,
+}
+
+struct Axis {
+    minimum: i32,
+    maximum: i32,
+}
+
+impl Axis {
+    fn scale(&self, value: i32, size: f64) -> f64 {
+        if self.maximum <= self.minimum {
+            return 0.0;
+        }
+        (value - self.minimum) as f64 / (self.maximum - self.minimum) as f64 * (size - 1.0)
+    }
+}
+
+struct Touchscreen {
+    device: Device,
+    x_axis: Axis,
+    y_axis: Axis,
+    slots: Vec<Slot>,
+    current_slot: usize,
+    // Events are dropped until the next report after the kernel buffer
+    // overflowed.
+    is_dropping: bool,
+
+    // With pass-through, the clone and the touches in its slots.
+    virtual_touchscreen: Option<VirtualTouchscreen>,
+    virtual_slots: Vec<Option<i32>>,
+}
+
+#[derive(PartialEq, Copy, Clone)]
+enum Frame {
+    // Position in device units.
+    Move(i32, i32),
+    Lift,
+}
+
+#[derive(PartialEq)]
+enum Decision {
+    Undecided,
+    Accepted,
+    Rejected,
+}
+
+// A touch of a grabbed device, with pass-through.
+struct HeldTouch {
+    touchscreen: usize,
+    decision: Decision,
+    // Frames since the touch began, replayed if it is rejected.
+    frames: Vec<Frame>,
+    is_lifted: bool,
+    virtual_slot: Option<usize>,
+}
+
+/// Reads the touches of touchscreen event devices.
+pub struct EvdevBackend {
+    touchscreens: Vec<Touchscreen>,
+    screen_width: f64,
+    screen_height: f64,
+    next_touch_id: i32,
+    events: VecDeque<TouchEvent>,
+    pass_through: bool,
+    held_touches: HashMap<i32, HeldTouch>,
+}
+
+// Sends the frame of a passed through touch to the clone.
+fn forward(touchscreen: &mut Touchscreen, touch: &mut HeldTouch, touch_id: i32, frame: Frame) {
+    let mut events = Vec::new();
+
+    let slot = match touch.virtual_slot {
+        Some(slot) => slot,
+        // Touch input is disabled, only touches already passed through are
+        // followed, until they are lifted.
+        None if evdev::is_grabbed(touchscreen.device.id) => return,
+        None => match touchscreen.virtual_slots.iter().position(Option::is_none) {
+            Some(slot) => {
+                if touchscreen.virtual_slots.iter().all(Option::is_none) {
+                    events.push((EV_KEY, BTN_TOUCH, 1));
+                }
+                touchscreen.virtual_slots[slot] = Some(touch_id);
+                touch.virtual_slot = Some(slot);
+                events.push((EV_ABS, ABS_MT_SLOT, slot as i32));
+                events.push((EV_ABS, ABS_MT_TRACKING_ID, touch_id));
+                slot
+            },
+            // More touches than the device has slots, cannot happen unless
+            // touches end while edgy is not reading.
+            None => return,
+        },
+    };
+    if events.is_empty() {
+        events.push((EV_ABS, ABS_MT_SLOT, slot as i32));
+    }
+
+    match frame {
+        Frame::Move(x, y) => {
+            events.extend_from_slice(&[(EV_ABS, ABS_MT_POSITION_X, x), (EV_ABS, ABS_MT_POSITION_Y, y)]);
+            // Single touch axes follow the touch in the first used slot.
+            if touchscreen.virtual_slots.iter().position(Option::is_some) == Some(slot) {
+                events.extend_from_slice(&[(EV_ABS, ABS_X, x), (EV_ABS, ABS_Y, y)]);
+            }
+        },
+        Frame::Lift => {
+            events.push((EV_ABS, ABS_MT_TRACKING_ID, -1));
+            touchscreen.virtual_slots[slot] = None;
+            touch.virtual_slot = None;
+            if touchscreen.virtual_slots.iter().all(Option::is_none) {
+                events.push((EV_KEY, BTN_TOUCH, 0));
+            }
+        },
+    }
+
+    if let Some(ref mut virtual_touchscreen) = touchscreen.virtual_touchscreen {
+        if let Err(error) = virtual_touchscreen.send(&events) {
+            println!("Could not pass a touch through: {}", error);
+        }
+    }
+}
+
+fn open_touchscreen(path: &Path, pass_through: bool) -> Result<Touchscreen, String> {
+    let device = Device::open(path)?;
+    if !device.has_axis(ABS_MT_SLOT) || !device.has_axis(ABS_MT_POSITION_X) || !device.has_axis(ABS_MT_POSITION_Y) {
+        return Err(format!("{} ({}) is not a multi-touch device.", path.display(), device.name));
+    }
+
+    let axis_info = |axis| device.axis_info(axis)
+        .ok_or_else(|| format!("Could not read the axes of {}.", path.display()));
+    let slot_info = axis_info(ABS_MT_SLOT)?;
+    let x_info = axis_info(ABS_MT_POSITION_X)?;
+    let y_info = axis_info(ABS_MT_POSITION_Y)?;
+
+    // Touchpads are read like touchscreens, with the edges of their surface.
+    // Their clicks and pointer motion would not be passed through.
+    let is_touchpad = !device.has_property(INPUT_PROP_DIRECT);
+    if is_touchpad && pass_through {
+        return Err(format!("{} ({}) is a touchpad, its touches cannot be passed through.", path.display(), device.name));
+    }
+
+    let virtual_touchscreen = if pass_through { Some(VirtualTouchscreen::clone_device(&device)?) } else { None };
+    let num_slots = slot_info.maximum.max(0) as usize + 1;
+
+    println!("Reading touches from {} {} ({}).", if is_touchpad { "touchpad" } else { "touchscreen" },
+             path.display(), device.name);
+    device.register();
+    Ok(Touchscreen {
+        x_axis: Axis { minimum: x_info.minimum, maximum: x_info.maximum },
+        y_axis: Axis { minimum: y_info.minimum, maximum: y_info.maximum },
+        slots: vec![Slot::default(); num_slots],
+        current_slot: slot_info.value.max(0) as usize,
+        is_dropping: false,
+        virtual_touchscreen: virtual_touchscreen,
+        virtual_slots: vec![None; num_slots],
+        device: device,
+    })
+}
+
+impl EvdevBackend {
+    /// Touch positions are scaled to the screen size, so that the touchscreens
+    /// cover the whole screen. With pass_through, grab() takes the devices
+    /// from other applications and touches that are not part of a gesture
+    /// are passed on through virtual touchscreens.
+    pub fn new(paths: &[PathBuf], screen_width: f64, screen_height: f64, pass_through: bool) -> Result<EvdevBackend, String> {
+        let touchscreens = paths.iter()
+            .map(|path| open_touchscreen(path, pass_through))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(EvdevBackend {
+            touchscreens: touchscreens,
+            screen_width: screen_width,
+            screen_height: screen_height,
+            next_touch_id: 1,
+            events: VecDeque::new(),
+            pass_through: pass_through,
+            held_touches: HashMap::new(),
+        })
+    }
+
+    /// Returns the id and name of each device, for the config.
+    pub fn devices(&self) -> Vec<(i32, String)> {
+        self.touchscreens.iter()
+            .map(|touchscreen| (touchscreen.device.id, touchscreen.device.name.clone()))
+            .collect()
+    }
+
+    fn read_events(&mut self) {
+        for i in 0..self.touchscreens.len() {
+            let events = match self.touchscreens[i].device.read_events() {
+                Ok(events) => events,
+                Err(error) => {
+                    println!("Could not read device #{}: {}", self.touchscreens[i].device.id, error);
+                    continue;
+                },
+            };
+            for event in events {
+                self.handle_input_event(i, &event);
+            }
+        }
+    }
+
+    fn handle_input_event(&mut self, index: usize, event: &libc::input_event) {
+        let touchscreen = &mut self.touchscreens[index];
+
+        match (event.type_, event.code) {
+            (EV_SYN, SYN_DROPPED) => {
+                touchscreen.is_dropping = true;
+            },
+            (EV_SYN, SYN_REPORT) if touchscreen.is_dropping => {
+                // The state of the slots is unknown, end the touches. Contacts
+                // still down are ignored until they are lifted.
+                touchscreen.is_dropping = false;
+                for slot in &mut touchscreen.slots {
+                    if let Some(touch_id) = slot.touch_id.take() {
+                        slot.ended = Some(touch_id);
+                    }
+                    slot.began = false;
+                    slot.moved = false;
+                }
+                self.report(index);
+            },
+            (EV_SYN, SYN_REPORT) => self.report(index),
+            _ if touchscreen.is_dropping => {},
+            (EV_ABS, ABS_MT_SLOT) => {
+                touchscreen.current_slot = event.value.max(0) as usize;
+            },
+            (EV_ABS, code) => {
+                let slot = match touchscreen.slots.get_mut(touchscreen.current_slot) {
+                    Some(slot) => slot,
+                    None => return,
+                };
+                match code {
+                    ABS_MT_TRACKING_ID if event.value < 0 => {
+                        if let Some(touch_id) = slot.touch_id.take() {
+                            slot.ended = Some(touch_id);
+                        }
+                        slot.began = false;
+                    },
+                    ABS_MT_TRACKING_ID => {
+                        if let Some(touch_id) = slot.touch_id {
+                            // Reused without lifting, end the old touch first.
+                            slot.ended = Some(touch_id);
+                        }
+                        slot.touch_id = Some(self.next_touch_id);
+                        self.next_touch_id = self.next_touch_id.wrapping_add(1).max(1);
+                        slot.began = true;
+                    },
+                    ABS_MT_POSITION_X => {
+                        slot.x = event.value;
+                        slot.moved = true;
+                    },
+                    ABS_MT_POSITION_Y => {
+                        slot.y = event.value;
+                        slot.moved = true;
+                    },
+                    _ => {},
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // Turns the changes of the slots since the last report into events.
+    fn report(&mut self, index: usize) {
+        let mut frames = Vec::new();
+        let touchscreen = &mut self.touchscreens[index];
+        for slot in &mut touchscreen.slots {
+            let x = touchscreen.x_axis.scale(slot.x, self.screen_width);
+            let y = touchscreen.y_axis.scale(slot.y, self.screen_height);
+
+            if let Some(touch_id) = slot.ended.take() {
+                self.events.push_back(TouchEvent::End { touch_id: touch_id, source_id: touchscreen.device.id, x: x, y: y });
+                frames.push((touch_id, Frame::Lift));
+            }
+            match slot.touch_id {
+                Some(touch_id) if slot.began => {
+                    self.events.push_back(TouchEvent::Begin {
+                        touch_id: touch_id,
+                        device_id: touchscreen.device.id,
+                        source_id: touchscreen.device.id,
+                        x: x,
+                        y: y,
+                    });
+                    if self.pass_through {
+                        self.held_touches.insert(touch_id, HeldTouch {
+                            touchscreen: index,
+                            decision: Decision::Undecided,
+                            frames: Vec::new(),
+                            is_lifted: false,
+                            virtual_slot: None,
+                        });
+                    }
+                    frames.push((touch_id, Frame::Move(slot.x, slot.y)));
+                },
+                Some(touch_id) if slot.moved => {
+                    self.events.push_back(TouchEvent::Update { touch_id: touch_id, source_id: touchscreen.device.id, x: x, y: y });
+                    frames.push((touch_id, Frame::Move(slot.x, slot.y)));
+                },
+                _ => {},
+            }
+            slot.began = false;
+            slot.moved = false;
+        }
+
+        for (touch_id, frame) in frames {
+            self.pass_frame(touch_id, frame);
+        }
+    }
+
+    // Holds the frame back until the touch is decided, or passes it through if
+    // it was rejected.
+    fn pass_frame(&mut self, touch_id: i32, frame: Frame) {
+        let remove = match self.held_touches.get_mut(&touch_id) {
+            None => return,
+            Some(touch) => {
+                touch.is_lifted = frame == Frame::Lift;
+                match touch.decision {
+                    Decision::Undecided => touch.frames.push(frame),
+                    Decision::Rejected => forward(&mut self.touchscreens[touch.touchscreen], touch, touch_id, frame),
+                    Decision::Accepted => {},
+                }
+                touch.is_lifted && touch.decision != Decision::Undecided
+            },
+        };
+        if remove {
+            self.held_touches.remove(&touch_id);
+        }
+    }
+
+    fn decide(&mut self, touch_id: i32, decision: Decision) {
+        let remove = match self.held_touches.get_mut(&touch_id) {
+            Some(touch) if touch.decision == Decision::Undecided => {
+                if decision == Decision::Rejected {
+                    for frame in touch.frames.drain(..).collect::<Vec<_>>() {
+                        forward(&mut self.touchscreens[touch.touchscreen], touch, touch_id, frame);
+                    }
+                }
+                touch.frames.clear();
+                touch.decision = decision;
+                touch.is_lifted
+            },
+            _ => return,
+        };
+        if remove {
+            self.held_touches.remove(&touch_id);
+        }
+    }
+}
+
+impl InputBackend for EvdevBackend {
+    fn grab(&mut self) -> Result<(), String> {
+        // Without pass-through the devices are read from the start, other
+        // applications keep receiving their touches.
+        if !self.pass_through {
+            return Ok(());
+        }
+
+        for touchscreen in &self.touchscreens {
+            touchscreen.device.grab_exclusively()
+                .map_err(|error| format!("Could not grab {}: {}", touchscreen.device.name, error))?;
+        }
+        Ok(())
+    }
+
+    fn ungrab(&mut self) {
+        if !self.pass_through {
+            return;
+        }
+
+        for touchscreen in &self.touchscreens {
+            if let Err(error) = touchscreen.device.ungrab_exclusively() {
+                println!("Could not ungrab {}: {}", touchscreen.device.name, error);
+            }
+        }
+    }
+
+    fn wait(&mut self, timeout: Option<Duration>, other_fds: &[c_int]) -> bool {
+        if self.events.is_empty() {
+            let fds: Vec<c_int> = self.touchscreens.iter()
+                .map(|touchscreen| touchscreen.device.fd())
+                .chain(other_fds.iter().cloned())
+                .collect();
+            if poll(&fds, timeout).into_iter().take(self.touchscreens.len()).any(|readable| readable) {
+                self.read_events();
+            }
+        }
+        !self.events.is_empty()
+    }
+
+    fn next_event(&mut self) -> Option<TouchEvent> {
+        if self.events.is_empty() {
+            self.read_events();
+        }
+        self.events.pop_front()
+    }
+
+    fn reject_touch(&mut self, touch_id: i32, _: i32) {
+        self.decide(touch_id, Decision::Rejected);
+    }
+
+    fn accept_touch(&mut self, touch_id: i32, _: i32) {
+        self.decide(touch_id, Decision::Accepted);
+    }
+
+    // The devices are given as paths, they are not selected again.
+    fn devices_changed(&mut self) -> bool {
+        false
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::{ErrorKind, Read};
+    use std::mem::{size_of, zeroed};
+    use std::os::fd::OwnedFd;
+    use std::os::unix::net::UnixStream;
+    use std::ptr;
+
+    const DEVICE: i32 = 7;
+
+    // A backend reading one touchscreen with two slots, whose axes range from
+    // 0 to 1024 like the screen, so positions are not scaled.
+    fn backend(virtual_touchscreen: Option<VirtualTouchscreen>) -> EvdevBackend {
+        let pass_through = virtual_touchscreen.is_some();
+        EvdevBackend {
+            touchscreens: vec![Touchscreen {
+                device: Device::fake(DEVICE, "Test Touchscreen"),
+                x_axis: Axis { minimum: 0, maximum: 1024 },
+                y_axis: Axis { minimum: 0, maximum: 1024 },
+                slots: vec![Slot::default(); 2],
+                current_slot: 0,
+                is_dropping: false,
+                virtual_touchscreen: virtual_touchscreen,
+                virtual_slots: vec![None; 2],
+            }],
+            screen_width: 1025.0,
+            screen_height: 1025.0,
+            next_touch_id: 1,
+            events: VecDeque::new(),
+            pass_through: pass_through,
+            held_touches: HashMap::new(),
+        }
+    }
+
+    fn feed(backend: &mut EvdevBackend, events: &[(u16, u16, i32)]) -> Vec<TouchEvent> {
+        for &(event_type, code, value) in events {
+            let mut event: libc::input_event = unsafe { zeroed() };
+            event.type_ = event_type;
+            event.code = code;
+            event.value = value;
+            backend.handle_input_event(0, &event);
+        }
+        backend.events.drain(..).collect()
+    }
+
+    const REPORT: (u16, u16, i32) = (EV_SYN, SYN_REPORT, 0);
+
+    fn begin(touch_id: i32, x: f64, y: f64) -> TouchEvent {
+        TouchEvent::Begin { touch_id: touch_id, device_id: DEVICE, source_id: DEVICE, x: x, y: y }
+    }
+
+    #[test]
+    fn touch_begins_moves_and_ends() {
+        let mut backend = backend(None);
+        assert_eq!(feed(&mut backend, &[(EV_ABS, ABS_MT_SLOT, 0), (EV_ABS, ABS_MT_TRACKING_ID, 40),
+                                        (EV_ABS, ABS_MT_POSITION_X, 100), (EV_ABS, ABS_MT_POSITION_Y, 200), REPORT]),
+                   vec![begin(1, 100.0, 200.0)]);
+        assert_eq!(feed(&mut backend, &[(EV_ABS, ABS_MT_POSITION_X, 150), REPORT]),
+                   vec![TouchEvent::Update { touch_id: 1, source_id: DEVICE, x: 150.0, y: 200.0 }]);
+        assert_eq!(feed(&mut backend, &[(EV_ABS, ABS_MT_TRACKING_ID, -1), REPORT]),
+                   vec![TouchEvent::End { touch_id: 1, source_id: DEVICE, x: 150.0, y: 200.0 }]);
+        // Nothing changed, nothing to report.
+        assert_eq!(feed(&mut backend, &[REPORT]), vec![]);
+    }
+
+    #[test]
+    fn slots_are_followed_separately() {
+        let mut backend = backend(None);
+        assert_eq!(feed(&mut backend, &[(EV_ABS, ABS_MT_TRACKING_ID, 40), (EV_ABS, ABS_MT_POSITION_X, 100),
+                                        (EV_ABS, ABS_MT_POSITION_Y, 100),
+                                        (EV_ABS, ABS_MT_SLOT, 1), (EV_ABS, ABS_MT_TRACKING_ID, 41),
+                                        (EV_ABS, ABS_MT_POSITION_X, 500), (EV_ABS, ABS_MT_POSITION_Y, 500), REPORT]),
+                   vec![begin(1, 100.0, 100.0), begin(2, 500.0, 500.0)]);
+        // The slot is kept between reports.
+        assert_eq!(feed(&mut backend, &[(EV_ABS, ABS_MT_POSITION_Y, 600), REPORT]),
+                   vec![TouchEvent::Update { touch_id: 2, source_id: DEVICE, x: 500.0, y: 600.0 }]);
+        assert_eq!(feed(&mut backend, &[(EV_ABS, ABS_MT_SLOT, 0), (EV_ABS, ABS_MT_TRACKING_ID, -1), REPORT]),
+                   vec![TouchEvent::End { touch_id: 1, source_id: DEVICE, x: 100.0, y: 100.0 }]);
+    }
+
+    #[test]
+    fn reused_tracking_id_ends_the_old_touch() {
+        let mut backend = backend(None);
+        feed(&mut backend, &[(EV_ABS, ABS_MT_TRACKING_ID, 40), (EV_ABS, ABS_MT_POSITION_X, 100),
+                             (EV_ABS, ABS_MT_POSITION_Y, 100), REPORT]);
+        // A new contact in the slot without the old one being lifted.
+        assert_eq!(feed(&mut backend, &[(EV_ABS, ABS_MT_TRACKING_ID, 41), (EV_ABS, ABS_MT_POSITION_X, 300), REPORT]),
+                   vec![TouchEvent::End { touch_id: 1, source_id: DEVICE, x: 300.0, y: 100.0 }, begin(2, 300.0, 100.0)]);
+        // A tracking id used again by the kernel is a new touch.
+        feed(&mut backend, &[(EV_ABS, ABS_MT_TRACKING_ID, -1), REPORT]);
+        assert_eq!(feed(&mut backend, &[(EV_ABS, ABS_MT_TRACKING_ID, 40), REPORT]),
+                   vec![begin(3, 300.0, 100.0)]);
+    }
+
+    #[test]
+    fn dropped_events_end_the_touches() {
+        let mut backend = backend(None);
+        feed(&mut backend, &[(EV_ABS, ABS_MT_TRACKING_ID, 40), (EV_ABS, ABS_MT_POSITION_X, 100),
+                             (EV_ABS, ABS_MT_POSITION_Y, 100), REPORT]);
+
+        // Events up to the next report are ignored, then the touches end.
+        assert_eq!(feed(&mut backend, &[(EV_SYN, SYN_DROPPED, 0), (EV_ABS, ABS_MT_POSITION_X, 900)]), vec![]);
+        assert_eq!(feed(&mut backend, &[REPORT]), vec![TouchEvent::End { touch_id: 1, source_id: DEVICE, x: 100.0, y: 100.0 }]);
+
+        // The contact still down is ignored until it is lifted.
+        assert_eq!(feed(&mut backend, &[(EV_ABS, ABS_MT_POSITION_X, 200), REPORT]), vec![]);
+        assert_eq!(feed(&mut backend, &[(EV_ABS, ABS_MT_TRACKING_ID, -1), REPORT]), vec![]);
+        assert_eq!(feed(&mut backend, &[(EV_ABS, ABS_MT_TRACKING_ID, 42), REPORT]), vec![begin(2, 200.0, 100.0)]);
+    }
+
+    #[test]
+    fn slots_beyond_the_device_are_ignored() {
+        let mut backend = backend(None);
+        assert_eq!(feed(&mut backend, &[(EV_ABS, ABS_MT_SLOT, 5), (EV_ABS, ABS_MT_TRACKING_ID, 40), REPORT]), vec![]);
+    }
+
+    // A pass-through backend, and the other end of its virtual touchscreen.
+    fn pass_through_backend() -> (EvdevBackend, UnixStream) {
+        let (file, other_end) = UnixStream::pair().unwrap();
+        other_end.set_nonblocking(true).unwrap();
+        let virtual_touchscreen = VirtualTouchscreen::from_file(File::from(OwnedFd::from(file)));
+        (backend(Some(virtual_touchscreen)), other_end)
+    }
+
+    // Returns the reports written to the virtual touchscreen so far, without
+    // their SYN_REPORT.
+    fn sent(other_end: &mut UnixStream) -> Vec<Vec<(u16, u16, i32)>> {
+        let mut bytes = Vec::new();
+        match other_end.read_to_end(&mut bytes) {
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {},
+            result => panic!("{:?}", result),
+        }
+
+        let mut reports = vec![Vec::new()];
+        for chunk in bytes.chunks(size_of::<libc::input_event>()) {
+            let event: libc::input_event = unsafe { ptr::read_unaligned(chunk.as_ptr() as *const _) };
+            if (event.type_, event.code) == (EV_SYN, SYN_REPORT) {
+                reports.push(Vec::new());
+            }
+            else {
+                reports.last_mut().unwrap().push((event.type_, event.code, event.value));
+            }
+        }
+        reports.pop();
+        reports
+    }
+
+    const DOWN: &[(u16, u16, i32)] = &[(EV_ABS, ABS_MT_TRACKING_ID, 40), (EV_ABS, ABS_MT_POSITION_X, 100),
+                                       (EV_ABS, ABS_MT_POSITION_Y, 200), REPORT];
+    const MOVE: &[(u16, u16, i32)] = &[(EV_ABS, ABS_MT_POSITION_X, 150), REPORT];
+    const UP: &[(u16, u16, i32)] = &[(EV_ABS, ABS_MT_TRACKING_ID, -1), REPORT];
+
+    fn replayed_down() -> Vec<(u16, u16, i32)> {
+        vec![(EV_KEY, BTN_TOUCH, 1), (EV_ABS, ABS_MT_SLOT, 0), (EV_ABS, ABS_MT_TRACKING_ID, 1),
+             (EV_ABS, ABS_MT_POSITION_X, 100), (EV_ABS, ABS_MT_POSITION_Y, 200), (EV_ABS, ABS_X, 100), (EV_ABS, ABS_Y, 200)]
+    }
+
+    fn replayed_move(x: i32) -> Vec<(u16, u16, i32)> {
+        vec![(EV_ABS, ABS_MT_SLOT, 0), (EV_ABS, ABS_MT_POSITION_X, x), (EV_ABS, ABS_MT_POSITION_Y, 200),
+             (EV_ABS, ABS_X, x), (EV_ABS, ABS_Y, 200)]
+    }
+
+    fn replayed_up() -> Vec<(u16, u16, i32)> {
+        vec![(EV_ABS, ABS_MT_SLOT, 0), (EV_ABS, ABS_MT_TRACKING_ID, -1), (EV_KEY, BTN_TOUCH, 0)]
+    }
+
+    #[test]
+    fn undecided_touches_are_held_back() {
+        let (mut backend, mut other_end) = pass_through_backend();
+        feed(&mut backend, DOWN);
+        feed(&mut backend, MOVE);
+        assert_eq!(sent(&mut other_end), Vec::<Vec<_>>::new());
+        assert_eq!(backend.held_touches[&1].frames.len(), 2);
+    }
+
+    #[test]
+    fn touch_rejected_before_lift_is_replayed_then_forwarded() {
+        let (mut backend, mut other_end) = pass_through_backend();
+        feed(&mut backend, DOWN);
+        feed(&mut backend, MOVE);
+        backend.reject_touch(1, DEVICE);
+        assert_eq!(sent(&mut other_end), vec![replayed_down(), replayed_move(150)]);
+
+        feed(&mut backend, &[(EV_ABS, ABS_MT_POSITION_X, 180), REPORT]);
+        assert_eq!(sent(&mut other_end), vec![replayed_move(180)]);
+        assert!(backend.held_touches.contains_key(&1));
+
+        feed(&mut backend, UP);
+        assert_eq!(sent(&mut other_end), vec![replayed_up()]);
+        assert!(backend.held_touches.is_empty());
+    }
+
+    #[test]
+    fn touch_rejected_after_lift_is_replayed_completely() {
+        let (mut backend, mut other_end) = pass_through_backend();
+        feed(&mut backend, DOWN);
+        feed(&mut backend, MOVE);
+        feed(&mut backend, UP);
+        assert_eq!(sent(&mut other_end), Vec::<Vec<_>>::new());
+
+        backend.reject_touch(1, DEVICE);
+        assert_eq!(sent(&mut other_end), vec![replayed_down(), replayed_move(150), replayed_up()]);
+        assert!(backend.held_touches.is_empty());
+    }
+
+    #[test]
+    fn accepted_touch_is_dropped() {
+        let (mut backend, mut other_end) = pass_through_backend();
+        feed(&mut backend, DOWN);
+        feed(&mut backend, MOVE);
+        backend.accept_touch(1, DEVICE);
+        assert!(backend.held_touches[&1].frames.is_empty());
+
+        feed(&mut backend, MOVE);
+        feed(&mut backend, UP);
+        assert_eq!(sent(&mut other_end), Vec::<Vec<_>>::new());
+        assert!(backend.held_touches.is_empty());
+    }
+
+    #[test]
+    fn touch_accepted_after_lift_is_forgotten() {
+        let (mut backend, mut other_end) = pass_through_backend();
+        feed(&mut backend, DOWN);
+        feed(&mut backend, UP);
+        backend.accept_touch(1, DEVICE);
+        assert_eq!(sent(&mut other_end), Vec::<Vec<_>>::new());
+        assert!(backend.held_touches.is_empty());
+    }
+
+    #[test]
+    fn single_touch_axes_follow_the_first_slot() {
+        let (mut backend, mut other_end) = pass_through_backend();
+        feed(&mut backend, DOWN);
+        backend.reject_touch(1, DEVICE);
+        feed(&mut backend, &[(EV_ABS, ABS_MT_SLOT, 1), (EV_ABS, ABS_MT_TRACKING_ID, 41),
+                             (EV_ABS, ABS_MT_POSITION_X, 500), (EV_ABS, ABS_MT_POSITION_Y, 600), REPORT]);
+        backend.reject_touch(2, DEVICE);
+        assert_eq!(sent(&mut other_end), vec![
+            replayed_down(),
+            vec![(EV_ABS, ABS_MT_SLOT, 1), (EV_ABS, ABS_MT_TRACKING_ID, 2),
+                 (EV_ABS, ABS_MT_POSITION_X, 500), (EV_ABS, ABS_MT_POSITION_Y, 600)],
+        ]);
+
+        // Once the first touch is lifted, they follow the second one.
+        feed(&mut backend, &[(EV_ABS, ABS_MT_SLOT, 0), (EV_ABS, ABS_MT_TRACKING_ID, -1), REPORT]);
+        feed(&mut backend, &[(EV_ABS, ABS_MT_SLOT, 1), (EV_ABS, ABS_MT_POSITION_X, 550), REPORT]);
+        assert_eq!(sent(&mut other_end), vec![
+            vec![(EV_ABS, ABS_MT_SLOT, 0), (EV_ABS, ABS_MT_TRACKING_ID, -1)],
+            vec![(EV_ABS, ABS_MT_SLOT, 1), (EV_ABS, ABS_MT_POSITION_X, 550), (EV_ABS, ABS_MT_POSITION_Y, 600),
+                 (EV_ABS, ABS_X, 550), (EV_ABS, ABS_Y, 600)],
+        ]);
+    }
+}
@@ -0,0 +1,190 @@
+//! The event loop: touches are read from an input backend and fed to the
+//! gesture detector, which runs the actions of the gestures made.
+
+use std::cell::RefCell;
+use std::os::raw::c_int;
+use std::time::Instant;
+
+use actions::find_actions;
+use backend::{handle_event, InputBackend, TouchEvent};
+use config::Config;
+use control;
+use dbus_service;
+use devicegrab;
+use events;
+use ewmh;
+use gesture_detector::{Gesture, GestureDetector};
+use json;
+use notifications;
+use overlay;
+use signals;
+
+/// Reads touch events from the backend and runs the actions of the gestures
+/// made. The backend should already be grabbed, with the signal handlers
+/// installed before that by signals::install.
+///
+/// Runs until SIGINT or SIGTERM is received, then rejects the touches not
+/// decided on, enables touch input again, ungrabs the backend and returns the
+/// signal.
+pub fn run_event_loop<B: InputBackend>(config:&Config, backend: B) -> c_int {
+
+    let backend = RefCell::new(backend);
+
+    let mut on_accept_touch = & mut |touch_id, device_id, source_id| {
+        backend.borrow_mut().accept_touch(touch_id, device_id);
+        events::emit("touch_accepted", events::touch_fields(touch_id, source_id));
+    };
+
+    let mut on_reject_touch = & mut |touch_id, device_id, source_id| {
+        backend.borrow_mut().reject_touch(touch_id, device_id);
+        events::emit("touch_rejected", events::touch_fields(touch_id, source_id));
+    };
+
+    // Only query the active window if there are window specific actions.
+    let has_window_actions = config.actions.iter().any(|action| action.window.is_some());
+
+    let service = if config.dbus_service {
+        match dbus_service::Service::new() {
+            Ok(service) => Some(RefCell::new(service)),
+            Err(error) => {
+                println!("Could not register on the session bus: {}", error);
+                None
+            },
+        }
+    }
+    else {
+        None
+    };
+
+    let mut on_gesture = &mut |gd : &mut GestureDetector, gesture: &Gesture| {
+        if let Some(ref service) = service {
+            service.borrow_mut().emit_gesture(gesture);
+        }
+
+        let mut fields = events::gesture_fields(gesture);
+        fields.push(("layer", json::Json::from(gd.layers.current())));
+        events::emit("gesture", fields);
+
+        let window_info = if has_window_actions { config.x11.as_ref().and_then(|x11| ewmh::get_active_window_info(x11)) } else { None };
+        let actions = find_actions(&config.actions, gd.layers.current(), gesture, window_info.as_ref());
+
+        if actions.is_empty() && config.notify_failed_gestures {
+            notifications::notify("No action for gesture",
+                                  &format!("From {} to {} with {} fingers",
+                                           gesture.side, gesture.direction, gesture.num_fingers));
+        }
+
+        if actions.is_empty() && gd.layers.is_temporary() {
+            // Unbound gestures leave temporary layers.
+            gd.layers.pop_all(config);
+        }
+
+        for action in actions {
+            (action.function)(gd, gesture);
+        }
+    };
+
+    let mut gesture_detector = GestureDetector::new(
+        &config,
+        on_accept_touch,
+        on_reject_touch,
+        on_gesture
+        );
+
+    // The initial layer may block touch.
+    let device_ids = config.device_ids.borrow().clone();
+    gesture_detector.layers.block_touch(config, &device_ids);
+
+    let mut control_socket = match config.control_socket {
+        Some(ref path) => match control::ControlSocket::new(path) {
+            Ok(control_socket) => Some(control_socket),
+            Err(error) => {
+                println!("Could not open the control socket: {}", error);
+                None
+            },
+        },
+        None => None,
+    };
+
+    let mut overlay = if config.show_overlay {
+        config.x11.clone().map(|x11| overlay::Overlay::new(x11, config))
+    }
+    else {
+        None
+    };
+
+    let signal = loop {
+        if let Some(signal) = signals::received() {
+            break signal;
+        }
+
+        // A pause for a while ends without any input.
+        if gesture_detector.resume_at().is_some_and(|resume_at| resume_at <= Instant::now()) {
+            control::resume(&mut gesture_detector);
+        }
+
+        let timeout = overlay.as_ref().and_then(|overlay| overlay.timeout()).into_iter()
+            .chain(control_socket.as_ref().and_then(control::ControlSocket::timeout))
+            .chain(gesture_detector.resume_at().map(|resume_at| resume_at.saturating_duration_since(Instant::now())))
+            .min();
+        let other_fds: Vec<c_int> = service.iter().filter_map(|service| service.borrow().fd())
+            .chain(control_socket.iter().flat_map(control::ControlSocket::fds))
+            .chain(signals::fd())
+            .collect();
+        let has_event = backend.borrow_mut().wait(timeout, &other_fds);
+
+        // Handled on every iteration, not only when there is no touch event,
+        // so that continuous touch input does not hold them up.
+        if let Some(ref mut overlay) = overlay {
+            overlay.handle_timeout();
+        }
+        if let Some(ref service) = service {
+            service.borrow_mut().dispatch(&mut gesture_detector);
+        }
+        if let Some(ref mut control_socket) = control_socket {
+            control_socket.dispatch(&mut gesture_detector);
+        }
+
+        let event = if has_event { backend.borrow_mut().next_event() } else { None };
+        if let Some(ref mut overlay) = overlay {
+            overlay.handle_events();
+        }
+
+        if backend.borrow_mut().devices_changed() {
+            let added = devicegrab::update_devices(config);
+            gesture_detector.layers.block_touch(config, &added);
+        }
+
+        let event = match event {
+            Some(event) => event,
+            None => continue,
+        };
+
+        // Whether a swipe was being made before this event.
+        let was_swiping = gesture_detector.progress().is_some_and(|progress| progress.direction.is_some());
+
+        if !handle_event(&mut gesture_detector, event) {
+            // The touch does not come from a device that is being listened to.
+            if let TouchEvent::Begin { touch_id, device_id, .. } = event {
+                backend.borrow_mut().reject_touch(touch_id, device_id);
+            }
+            continue;
+        }
+
+        if let Some(ref mut overlay) = overlay {
+            overlay.update(&gesture_detector);
+        }
+
+        if config.notify_failed_gestures && was_swiping && gesture_detector.progress().is_none() &&
+           gesture_detector.last_gesture_was_ruined()
+        {
+            notifications::notify("Gesture not recognized", "");
+        }
+    };
+
+    println!("Received signal {}, exiting.", signal);
+    gesture_detector.reject_all();
+    devicegrab::enable_touch(config, &config.device_ids.borrow());
+    backend.borrow_mut().ungrab();
+    signal
+}
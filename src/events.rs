@@ -0,0 +1,79 @@
+//! Events about gestures, touches and state changes, written as JSON lines to
+//! the subscribers of the control socket and, with --emit json, to stdout.
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixStream;
+
+use gesture_detector::Gesture;
+use json::Json;
+
+use libc;
+
+thread_local! {
+    static SUBSCRIBERS: RefCell<Vec<UnixStream>> = const { RefCell::new(Vec::new()) };
+    static STDOUT: RefCell<Option<File>> = const { RefCell::new(None) };
+}
+
+/// Writes the events to stdout. The log, and the output of commands, is moved
+/// to stderr, so that stdout only has events.
+pub fn emit_to_stdout() {
+    unsafe {
+        let stdout = libc::dup(libc::STDOUT_FILENO);
+        if stdout < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            panic!("Could not redirect the log to stderr.");
+        }
+        STDOUT.with(|file| *file.borrow_mut() = Some(File::from_raw_fd(stdout)));
+    }
+}
+
+/// Sends the events emitted from now on to the stream, until writing fails.
+pub fn subscribe(stream: UnixStream) {
+    SUBSCRIBERS.with(|subscribers| subscribers.borrow_mut().push(stream));
+}
+
+/// Writes the event, an object with "event" and the fields, to stdout if
+/// enabled and to the subscribers.
+pub fn emit(event: &str, fields: Vec<(&'static str, Json)>) {
+    let mut object = vec![("event", Json::from(event))];
+    object.extend(fields);
+    let line = format!("{}\n", Json::Object(object));
+
+    STDOUT.with(|file| {
+        if let Some(ref mut file) = *file.borrow_mut() {
+            // E.g. the reader has gone away, which should not stop edgy.
+            let _ = file.write_all(line.as_bytes());
+        }
+    });
+
+    // Subscribers that have gone away are dropped.
+    SUBSCRIBERS.with(|subscribers| {
+        subscribers.borrow_mut().retain_mut(|stream| stream.write_all(line.as_bytes()).is_ok());
+    });
+}
+
+/// The fields describing a touch. The device is the one the touch came from,
+/// like the device of gestures, rather than the master device it is accepted
+/// or rejected on.
+pub fn touch_fields(touch_id: i32, device_id: i32) -> Vec<(&'static str, Json)> {
+    vec![
+        ("touch", Json::from(touch_id)),
+        ("device", Json::from(device_id)),
+    ]
+}
+
+/// The fields describing a gesture.
+pub fn gesture_fields(gesture: &Gesture) -> Vec<(&'static str, Json)> {
+    vec![
+        ("side", Json::from(gesture.side.to_string())),
+        ("direction", Json::from(gesture.direction.to_string())),
+        ("fingers", Json::from(gesture.num_fingers)),
+        ("start_x", Json::from(gesture.start_x)),
+        ("start_y", Json::from(gesture.start_y)),
+        ("end_x", Json::from(gesture.end_x)),
+        ("end_y", Json::from(gesture.end_y)),
+        ("device", Json::from(gesture.device_id)),
+    ]
+}
@@ -0,0 +1,311 @@
+//! Window management through EWMH client messages sent to the root window,
+//! and querying information about the active window.
+//! See <https://specifications.freedesktop.org/wm-spec/latest/>
+
+use std::ffi::{CStr, CString};
+use std::mem::zeroed;
+use std::os::raw::{c_char, c_int, c_long, c_uchar, c_ulong};
+use std::ptr::null_mut;
+use std::slice;
+use x11::xlib;
+use xconn::XConnection;
+
+// Source indication in client messages: the request comes from a pager or
+// other direct user action, not from an application.
+const SOURCE_PAGER: c_long = 2;
+
+const NET_WM_STATE_TOGGLE: c_long = 2;
+const NET_WM_STATE_ADD: c_long = 1;
+const NET_WM_STATE_REMOVE: c_long = 0;
+
+/// Returns the atom with the name, creating it if needed.
+pub fn intern_atom(x11: &XConnection, name: &str) -> xlib::Atom {
+    let c_name = CString::new(name).unwrap();
+    unsafe { xlib::XInternAtom(x11.display(), c_name.as_ptr(), xlib::False) }
+}
+
+/// Returns the items of a 32-bit property, e.g. CARDINAL or WINDOW.
+pub fn get_property_longs(x11: &XConnection, window: xlib::Window, property: &str, property_type: xlib::Atom) -> Option<Vec<c_ulong>> {
+    let mut actual_type = 0;
+    let mut actual_format = 0;
+    let mut num_items = 0;
+    let mut bytes_after = 0;
+    let mut data: *mut c_uchar = null_mut();
+
+    unsafe {
+        if xlib::XGetWindowProperty(x11.display(),
+                                    window,
+                                    intern_atom(x11, property),
+                                    0,
+                                    1024,
+                                    xlib::False,
+                                    property_type,
+                                    &mut actual_type,
+                                    &mut actual_format,
+                                    &mut num_items,
+                                    &mut bytes_after,
+                                    &mut data) != xlib::Success as c_int || data.is_null()
+        {
+            return None;
+        }
+
+        // Format 32 items are returned as longs.
+        let result = if actual_type == property_type && actual_format == 32 {
+            Some(slice::from_raw_parts(data as *const c_ulong, num_items as usize).to_vec())
+        }
+        else {
+            None
+        };
+
+        xlib::XFree(data as *mut _);
+        result
+    }
+}
+
+// Returns the contents of an 8-bit property, e.g. UTF8_STRING.
+fn get_property_bytes(x11: &XConnection, window: xlib::Window, property: &str, property_type: xlib::Atom) -> Option<Vec<u8>> {
+    let mut actual_type = 0;
+    let mut actual_format = 0;
+    let mut num_items = 0;
+    let mut bytes_after = 0;
+    let mut data: *mut c_uchar = null_mut();
+
+    unsafe {
+        if xlib::XGetWindowProperty(x11.display(),
+                                    window,
+                                    intern_atom(x11, property),
+                                    0,
+                                    1024,
+                                    xlib::False,
+                                    property_type,
+                                    &mut actual_type,
+                                    &mut actual_format,
+                                    &mut num_items,
+                                    &mut bytes_after,
+                                    &mut data) != xlib::Success as c_int || data.is_null()
+        {
+            return None;
+        }
+
+        let result = if actual_type == property_type && actual_format == 8 {
+            Some(slice::from_raw_parts(data, num_items as usize).to_vec())
+        }
+        else {
+            None
+        };
+
+        xlib::XFree(data as *mut _);
+        result
+    }
+}
+
+fn get_root_cardinal(x11: &XConnection, property: &str) -> Option<c_ulong> {
+    get_property_longs(x11, x11.root_window(), property, xlib::XA_CARDINAL)
+        .and_then(|values| values.first().cloned())
+}
+
+/// Returns the window in _NET_ACTIVE_WINDOW, if any.
+pub fn get_active_window(x11: &XConnection) -> Option<xlib::Window> {
+    get_property_longs(x11, x11.root_window(), "_NET_ACTIVE_WINDOW", xlib::XA_WINDOW)
+        .and_then(|values| values.first().cloned())
+        .and_then(|window| if window != 0 { Some(window) } else { None })
+}
+
+/// Identifies the active window for window specific actions.
+pub struct WindowInfo {
+    /// WM_CLASS class name.
+    pub class: String,
+    /// WM_CLASS instance name.
+    pub instance: String,
+    /// _NET_WM_NAME, or WM_NAME if not set.
+    pub title: String,
+}
+
+unsafe fn take_x_string(string: *mut c_char) -> String {
+    if string.is_null() {
+        return String::new();
+    }
+    let result = CStr::from_ptr(string).to_string_lossy().into_owned();
+    xlib::XFree(string as *mut _);
+    result
+}
+
+/// Returns the class, instance and title of the window, empty if not set.
+pub fn get_window_info(x11: &XConnection, window: xlib::Window) -> WindowInfo {
+    let mut class_hint = xlib::XClassHint {
+        res_name: null_mut(),
+        res_class: null_mut(),
+    };
+
+    let (instance, class) = unsafe {
+        if xlib::XGetClassHint(x11.display(), window, &mut class_hint) != 0 {
+            (take_x_string(class_hint.res_name), take_x_string(class_hint.res_class))
+        }
+        else {
+            (String::new(), String::new())
+        }
+    };
+
+    // Prefer the UTF-8 title set by modern applications over WM_NAME.
+    let title = match get_property_bytes(x11, window, "_NET_WM_NAME", intern_atom(x11, "UTF8_STRING")) {
+        Some(title) => String::from_utf8_lossy(&title).into_owned(),
+        None => unsafe {
+            let mut name: *mut c_char = null_mut();
+            xlib::XFetchName(x11.display(), window, &mut name);
+            take_x_string(name)
+        },
+    };
+
+    WindowInfo {
+        class: class,
+        instance: instance,
+        title: title,
+    }
+}
+
+/// Returns the class, instance and title of the active window, if any.
+pub fn get_active_window_info(x11: &XConnection) -> Option<WindowInfo> {
+    get_active_window(x11).map(|window| get_window_info(x11, window))
+}
+
+fn send_client_message(x11: &XConnection, window: xlib::Window, message_type: &str, data: [c_long; 5]) {
+    unsafe {
+        let mut event: xlib::XClientMessageEvent = zeroed();
+        event.type_ = xlib::ClientMessage;
+        event.window = window;
+        event.message_type = intern_atom(x11, message_type);
+        event.format = 32;
+        for (i, &value) in data.iter().enumerate() {
+            event.data.set_long(i, value);
+        }
+
+        let mut event = xlib::XEvent::from(event);
+        xlib::XSendEvent(x11.display(),
+                         x11.root_window(),
+                         xlib::False,
+                         xlib::SubstructureRedirectMask | xlib::SubstructureNotifyMask,
+                         &mut event);
+        xlib::XFlush(x11.display());
+    }
+}
+
+fn with_active_window<F>(x11: &XConnection, f: F) where F: FnOnce(xlib::Window) {
+    match get_active_window(x11) {
+        Some(window) => f(window),
+        None => println!("There is no active window."),
+    }
+}
+
+/// Asks the window manager to close the active window.
+pub fn close_active_window(x11: &XConnection) {
+    with_active_window(x11, |window| {
+        send_client_message(x11, window, "_NET_CLOSE_WINDOW", [xlib::CurrentTime as c_long, SOURCE_PAGER, 0, 0, 0]);
+    });
+}
+
+/// Iconifies the active window.
+pub fn minimize_active_window(x11: &XConnection) {
+    // EWMH has no message for minimizing, use the ICCCM WM_CHANGE_STATE.
+    with_active_window(x11, |window| {
+        unsafe {
+            xlib::XIconifyWindow(x11.display(), window, xlib::XDefaultScreen(x11.display()));
+            xlib::XFlush(x11.display());
+        }
+    });
+}
+
+fn set_maximized(x11: &XConnection, window: xlib::Window, action: c_long) {
+    send_client_message(x11, window,
+                        "_NET_WM_STATE",
+                        [action,
+                         intern_atom(x11, "_NET_WM_STATE_MAXIMIZED_VERT") as c_long,
+                         intern_atom(x11, "_NET_WM_STATE_MAXIMIZED_HORZ") as c_long,
+                         SOURCE_PAGER,
+                         0]);
+}
+
+fn is_maximized(x11: &XConnection, window: xlib::Window) -> bool {
+    let maximized_vert = intern_atom(x11, "_NET_WM_STATE_MAXIMIZED_VERT");
+    let maximized_horz = intern_atom(x11, "_NET_WM_STATE_MAXIMIZED_HORZ");
+    get_property_longs(x11, window, "_NET_WM_STATE", xlib::XA_ATOM)
+        .map(|states| states.contains(&maximized_vert) || states.contains(&maximized_horz))
+        .unwrap_or(false)
+}
+
+/// Maximizes the active window, or restores it if it is maximized.
+pub fn toggle_maximize_active_window(x11: &XConnection) {
+    with_active_window(x11, |window| set_maximized(x11, window, NET_WM_STATE_TOGGLE));
+}
+
+/// Switches to the desktop offset desktops away from the current one,
+/// wrapping around at the ends.
+pub fn switch_desktop(x11: &XConnection, offset: i32) {
+    let (current, count) = match (get_root_cardinal(x11, "_NET_CURRENT_DESKTOP"),
+                                  get_root_cardinal(x11, "_NET_NUMBER_OF_DESKTOPS")) {
+        (Some(current), Some(count)) if count > 0 => (current as i64, count as i64),
+        _ => {
+            println!("The window manager does not support desktops.");
+            return;
+        },
+    };
+
+    let desktop = ((current + offset as i64) % count + count) % count;
+    send_client_message(x11, x11.root_window(), "_NET_CURRENT_DESKTOP", [desktop as c_long, xlib::CurrentTime as c_long, 0, 0, 0]);
+}
+
+/// Shows the desktop, or the windows again if the desktop is shown.
+pub fn toggle_show_desktop(x11: &XConnection) {
+    let showing = get_root_cardinal(x11, "_NET_SHOWING_DESKTOP").unwrap_or(0) != 0;
+    send_client_message(x11, x11.root_window(), "_NET_SHOWING_DESKTOP", [!showing as c_long, 0, 0, 0, 0]);
+}
+
+/// Moves the active window to the monitor offset monitors to the right (or to
+/// the left, if negative) of its current one, keeping its relative position.
+pub fn move_active_window_to_monitor(x11: &XConnection, offset: i32) {
+    with_active_window(x11, |window| {
+        let outputs = x11.get_outputs();
+        if outputs.len() < 2 {
+            println!("There is only one monitor.");
+            return;
+        }
+
+        let (mut x, mut y) = (0, 0);
+        let mut attributes: xlib::XWindowAttributes = unsafe { zeroed() };
+        unsafe {
+            let mut child = 0;
+            if xlib::XGetWindowAttributes(x11.display(), window, &mut attributes) == 0 ||
+               xlib::XTranslateCoordinates(x11.display(), window, x11.root_window(), 0, 0, &mut x, &mut y, &mut child) == 0
+            {
+                println!("Could not get the position of the active window.");
+                return;
+            }
+        }
+
+        let center_x = x as f64 + attributes.width as f64 / 2.0;
+        let center_y = y as f64 + attributes.height as f64 / 2.0;
+        let current = outputs.iter()
+            .position(|output| output.contains(center_x, center_y))
+            .unwrap_or(0) as i32;
+        let count = outputs.len() as i32;
+        let source = &outputs[current as usize];
+        let target = &outputs[(((current + offset) % count + count) % count) as usize];
+
+        let new_x = target.x + (x - source.x).min(target.width as i32 - attributes.width).max(0);
+        let new_y = target.y + (y - source.y).min(target.height as i32 - attributes.height).max(0);
+
+        // Window managers do not move maximized windows.
+        let was_maximized = is_maximized(x11, window);
+        if was_maximized {
+            set_maximized(x11, window, NET_WM_STATE_REMOVE);
+        }
+
+        // StaticGravity, so that the position refers to the client window
+        // rather than the frame, and flags for setting x and y.
+        let flags = xlib::StaticGravity as c_long | 1 << 8 | 1 << 9 | SOURCE_PAGER << 12;
+        send_client_message(x11, window, "_NET_MOVERESIZE_WINDOW", [flags, new_x as c_long, new_y as c_long, 0, 0]);
+
+        if was_maximized {
+            set_maximized(x11, window, NET_WM_STATE_ADD);
+        }
+    });
+}
@@ -0,0 +1,144 @@
+// Synthesizes keyboard and mouse events with the XTest extension.
+
+use std::ffi::CString;
+use std::str;
+use x11::{xlib, xtest};
+use xconn::XConnection;
+
+// Keys that are pressed together, e.g. Ctrl+Alt+Delete. The keys are pressed
+// in order and released in reverse order.
+pub type KeyCombo = Vec<xlib::KeySym>;
+
+fn modifier_keysym_name(name: &str) -> Option<&'static str> {
+    match name.to_lowercase().as_str() {
+        "super" | "win" | "logo" => Some("Super_L"),
+        "ctrl" | "control" => Some("Control_L"),
+        "alt" => Some("Alt_L"),
+        "altgr" => Some("ISO_Level3_Shift"),
+        "shift" => Some("Shift_L"),
+        "meta" => Some("Meta_L"),
+        "hyper" => Some("Hyper_L"),
+        _ => None,
+    }
+}
+
+fn string_to_keysym(name: &str) -> xlib::KeySym {
+    match CString::new(name) {
+        Ok(name) => unsafe { xlib::XStringToKeysym(name.as_ptr()) },
+        Err(_) => xlib::NoSymbol as xlib::KeySym,
+    }
+}
+
+fn parse_key(name: &str) -> Option<xlib::KeySym> {
+    let keysym = string_to_keysym(modifier_keysym_name(name).unwrap_or(name));
+    if keysym != xlib::NoSymbol as xlib::KeySym {
+        return Some(keysym);
+    }
+
+    // Keysym names are case sensitive, allow e.g. "tab" for "Tab".
+    let mut chars = name.chars();
+    let capitalized = match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+        None => return None,
+    };
+
+    let keysym = string_to_keysym(&capitalized);
+    if keysym != xlib::NoSymbol as xlib::KeySym {
+        Some(keysym)
+    }
+    else {
+        None
+    }
+}
+
+// Parses space-separated key combinations, e.g. "ctrl+c ctrl+v".
+pub fn parse_key_combos(input: &str) -> Result<Vec<KeyCombo>, &'static str> {
+    let mut combos = Vec::new();
+    for combo_str in input.split_whitespace() {
+        let mut combo = KeyCombo::new();
+        for key_name in combo_str.split('+') {
+            match parse_key(key_name) {
+                Some(keysym) => combo.push(keysym),
+                None => return Err("Unknown key name."),
+            }
+        }
+        combos.push(combo);
+    }
+
+    if combos.is_empty() {
+        return Err("No keys given.");
+    }
+
+    Ok(combos)
+}
+
+fn has_xtest(x11: &XConnection) -> bool {
+    let mut event_base = 0;
+    let mut error_base = 0;
+    let mut major_version = 0;
+    let mut minor_version = 0;
+
+    unsafe {
+        xtest::XTestQueryExtension(x11.display(),
+                                   &mut event_base,
+                                   &mut error_base,
+                                   &mut major_version,
+                                   &mut minor_version) == xlib::True
+    }
+}
+
+// Pointer buttons, as numbered by X.
+pub const BUTTON_LEFT: u32 = 1;
+pub const BUTTON_MIDDLE: u32 = 2;
+pub const BUTTON_RIGHT: u32 = 3;
+pub const BUTTON_SCROLL_UP: u32 = 4;
+pub const BUTTON_SCROLL_DOWN: u32 = 5;
+pub const BUTTON_SCROLL_LEFT: u32 = 6;
+pub const BUTTON_SCROLL_RIGHT: u32 = 7;
+
+pub fn send_key_combos(x11: &XConnection, combos: &[KeyCombo]) {
+    if !has_xtest(x11) {
+        println!("XTest extension is not available, cannot send keys.");
+        return;
+    }
+
+    unsafe {
+        for combo in combos {
+            let keycodes: Vec<xlib::KeyCode> = combo.iter()
+                .map(|&keysym| xlib::XKeysymToKeycode(x11.display(), keysym))
+                .collect();
+
+            if keycodes.contains(&0) {
+                println!("Key combination cannot be typed with the current keyboard mapping.");
+                continue;
+            }
+
+            for &keycode in &keycodes {
+                xtest::XTestFakeKeyEvent(x11.display(), keycode as u32, xlib::True, xlib::CurrentTime);
+            }
+            for &keycode in keycodes.iter().rev() {
+                xtest::XTestFakeKeyEvent(x11.display(), keycode as u32, xlib::False, xlib::CurrentTime);
+            }
+        }
+
+        xlib::XFlush(x11.display());
+    }
+}
+
+// Presses and releases the button count times at the current pointer
+// position. Scrolling is done with buttons 4-7, one step per click.
+pub fn click_button(x11: &XConnection, button: u32, count: u32) {
+    if !has_xtest(x11) {
+        println!("XTest extension is not available, cannot click.");
+        return;
+    }
+
+    unsafe {
+        for _ in 0..count {
+            xtest::XTestFakeButtonEvent(x11.display(), button, xlib::True, xlib::CurrentTime);
+            xtest::XTestFakeButtonEvent(x11.display(), button, xlib::False, xlib::CurrentTime);
+        }
+
+        xlib::XFlush(x11.display());
+    }
+}
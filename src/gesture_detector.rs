@@ -1,68 +1,171 @@
+//! Recognizes swipes that start at an edge of the screen from touch begin,
+//! update and end events, and decides which touches are part of them.
+
 use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
 
 use config::Config;
+use layers::LayerStack;
 
 #[derive(Debug, Copy, Clone)]
 struct Touch {
     touch_id: i32,
     device_id: i32,
+    // The physical device the touch came from (device_id is the master).
+    source_id: i32,
     start_x: f64,
     start_y: f64,
     // Is the touch accepted or rejected.
     is_decided: bool,
 }
 
+/// The edge of the screen a gesture starts at.
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub enum Side {
+    /// The top edge.
     Top,
+    /// The right edge.
     Right,
+    /// The bottom edge.
     Bottom,
+    /// The left edge.
     Left,
 }
 
+/// The direction the touches of a gesture move in.
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub enum Direction {
+    /// Towards the top edge.
     Up,
+    /// Towards the right edge.
     Right,
+    /// Towards the bottom edge.
     Down,
+    /// Towards the left edge.
     Left,
 }
 
-pub type GestureHook = FnMut(Side, Direction, u32);
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Side::Top => "top",
+            Side::Right => "right",
+            Side::Bottom => "bottom",
+            Side::Left => "left",
+        })
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Direction::Up => "up",
+            Direction::Right => "right",
+            Direction::Down => "down",
+            Direction::Left => "left",
+        })
+    }
+}
+
+/// A recognized gesture, as passed to the gesture hook.
+#[derive(Debug, Copy, Clone)]
+pub struct Gesture {
+    /// The edge the gesture started at.
+    pub side: Side,
+    /// The direction the touches moved in.
+    pub direction: Direction,
+    /// The number of touches in the gesture, which need not have been down
+    /// at the same time.
+    pub num_fingers: u32,
+    /// Where the first accepted touch of the gesture started.
+    pub start_x: f64,
+    /// See start_x.
+    pub start_y: f64,
+    /// Where the last touch of the gesture ended.
+    pub end_x: f64,
+    /// See end_x.
+    pub end_y: f64,
+    /// Source device of the first accepted touch.
+    pub device_id: i32,
+}
+
+/// State of the gesture being made, e.g. for showing feedback.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct GestureProgress {
+    /// The edge the gesture started at.
+    pub side: Side,
+    /// None until a touch has moved far enough.
+    pub direction: Option<Direction>,
+    /// The number of touches so far.
+    pub num_fingers: u32,
+    /// Whether the gesture will not be recognized, e.g. because a touch moved
+    /// in the wrong direction.
+    pub is_ruined: bool,
+}
+
+/// A callback for gestures, with their side, direction and number of fingers.
+pub type GestureHook = dyn FnMut(Side, Direction, u32);
 
+/// Recognizes gestures from the touch events passed to it. Touches that may
+/// be part of a gesture are held until they are known to be, then accepted,
+/// and other touches are rejected, so that other applications get them.
 pub struct GestureDetector<'a> {
+    /// The settings, including the actions of the gestures.
     pub config: & 'a Config,
+    /// Layer of actions currently in use.
+    pub layers: LayerStack,
     // Total number of touches in the current gesture. Note that they might not 
     // be down at the same time.
     current_num_touches: u32,
     current_side: Option<Side>,
     current_direction: Option<Direction>,
+    // Start position and source device of the first accepted touch.
+    current_start: Option<(f64, f64, i32)>,
     // If true, there has been an unrecognized or conflicting touch. Thus, the 
     // gesture is not detected.
     current_is_ruined: bool,
+    // Whether the previous gesture ended ruined.
+    last_is_ruined: bool,
+
+    // While paused, new touches are rejected. Pausing can be limited to last
+    // until resume_at.
+    paused: bool,
+    resume_at: Option<Instant>,
 
-    // Currently pressed touches, keyed by touch id
-    active_touches: HashMap<i32, Touch>,
+    // Currently pressed touches, keyed by source device and touch id, as touch
+    // ids are only unique per device.
+    active_touches: HashMap<(i32, i32), Touch>,
 
-    on_accept_touch: & 'a mut (FnMut(i32, i32) + 'a),
-    on_reject_touch: & 'a mut (FnMut(i32, i32) + 'a),
+    // Called with the touch id, the device to accept or reject it on and the
+    // device it came from.
+    on_accept_touch: & 'a mut (dyn FnMut(i32, i32, i32) + 'a),
+    on_reject_touch: & 'a mut (dyn FnMut(i32, i32, i32) + 'a),
 
-    on_gesture: Option<& 'a mut (FnMut(&mut GestureDetector, Side, Direction, u32) + 'a)>,
+    on_gesture: Option<& 'a mut (dyn FnMut(&mut GestureDetector, &Gesture) + 'a)>,
 }
 
 impl <'a>GestureDetector<'a> {
+    /// on_accept_touch and on_reject_touch are called with the touch id, the
+    /// device to accept or reject it on and the device it came from, and
+    /// on_gesture with each recognized gesture.
     pub fn new(config: & 'a Config,
-               on_accept_touch: & 'a mut (FnMut (i32, i32) + 'a),
-               on_reject_touch: & 'a mut (FnMut (i32, i32) + 'a),
-               on_gesture: & 'a mut (FnMut (&mut GestureDetector, Side, Direction, u32) + 'a))
+               on_accept_touch: & 'a mut (dyn FnMut(i32, i32, i32) + 'a),
+               on_reject_touch: & 'a mut (dyn FnMut(i32, i32, i32) + 'a),
+               on_gesture: & 'a mut (dyn FnMut(&mut GestureDetector, &Gesture) + 'a))
         -> GestureDetector<'a>
         {
             GestureDetector {
                 config: config,
+                layers: LayerStack::new(&config.initial_layer),
                 current_num_touches: 0,
                 current_side: None,
                 current_direction: None,
+                current_start: None,
                 current_is_ruined: false,
+                last_is_ruined: false,
+                paused: false,
+                resume_at: None,
                 active_touches: HashMap::new(),
                 on_accept_touch: on_accept_touch,
                 on_reject_touch: on_reject_touch,
@@ -97,16 +200,71 @@ impl <'a>GestureDetector<'a> {
         }
     }
 
-    pub fn handle_touch_start(&mut self, touch_id:i32, device_id:i32, x:f64, y:f64) {
+    /// Returns None if no gesture is being made.
+    pub fn progress(&self) -> Option<GestureProgress> {
+        self.current_side.map(|side| GestureProgress {
+            side: side,
+            direction: self.current_direction,
+            num_fingers: self.current_num_touches.max(self.active_touches.len() as u32),
+            is_ruined: self.current_is_ruined,
+        })
+    }
+
+    /// Returns whether the previous gesture ended without being recognized.
+    pub fn last_gesture_was_ruined(&self) -> bool {
+        self.last_is_ruined
+    }
+
+    /// Rejects new touches, until resumed or for the duration if given.
+    pub fn pause(&mut self, duration: Option<Duration>) {
+        self.paused = true;
+        self.resume_at = duration.map(|duration| Instant::now() + duration);
+    }
+
+    /// Detects gestures again after pause().
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.resume_at = None;
+    }
+
+    /// When a pause for a while ends, None if not paused or paused until
+    /// resumed.
+    pub fn resume_at(&self) -> Option<Instant> {
+        if self.paused { self.resume_at } else { None }
+    }
+
+    /// Returns whether new touches are rejected.
+    pub fn is_paused(&self) -> bool {
+        self.paused && self.resume_at.is_none_or(|resume_at| Instant::now() < resume_at)
+    }
+
+    /// Rejects the touches not decided on yet and forgets the current
+    /// gesture, e.g. before exiting.
+    pub fn reject_all(&mut self) {
+        reject_touches(&mut self.active_touches, self.on_reject_touch);
+        self.reset_state();
+    }
+
+    /// Handles a new touch. A touch outside the edge zones, on another edge
+    /// than the gesture being made, or while paused is rejected, and ruins
+    /// the gesture being made.
+    pub fn handle_touch_start(&mut self, touch_id:i32, device_id:i32, source_id:i32, x:f64, y:f64) {
+        self.last_is_ruined = false;
+
+        if self.is_paused() {
+            (*self.on_reject_touch)(touch_id, device_id, source_id);
+            return;
+        }
+
         if self.current_is_ruined {
-            (*self.on_reject_touch)(touch_id, device_id);
+            (*self.on_reject_touch)(touch_id, device_id, source_id);
             return;
         }
 
         if let Some(side) = self.get_touch_side(x, y) {
             match self.current_side {
                 Some(current_side) if current_side != side => {
-                    (*self.on_reject_touch)(touch_id, device_id);
+                    (*self.on_reject_touch)(touch_id, device_id, source_id);
                     if !self.active_touches.is_empty() {
                         // Don't mess up the state if there is no active gesture.
                         self.current_is_ruined = true;
@@ -119,16 +277,17 @@ impl <'a>GestureDetector<'a> {
                 },
             }
 
-            self.active_touches.insert(touch_id, Touch { 
+            self.active_touches.insert((source_id, touch_id), Touch { 
                 touch_id: touch_id,
                 device_id: device_id,
+                source_id: source_id,
                 start_x: x,
                 start_y: y, 
                 is_decided: false,
             });
         }
         else {
-            (*self.on_reject_touch)(touch_id, device_id);
+            (*self.on_reject_touch)(touch_id, device_id, source_id);
             if !self.active_touches.is_empty() {
                 // Don't mess up the state if there is no active gesture.
                 self.current_is_ruined = true;
@@ -137,17 +296,21 @@ impl <'a>GestureDetector<'a> {
         }
     }
 
-    pub fn handle_touch_end(&mut self, touch_id:i32, x:f64, y:f64) {
-        if !self.active_touches.contains_key(&touch_id) {
+    /// Handles a lifted touch. When the last touch of a gesture is lifted, the
+    /// gesture hook is called if it was recognized, otherwise its touches are
+    /// rejected.
+    pub fn handle_touch_end(&mut self, touch_id:i32, source_id:i32, x:f64, y:f64) {
+        let key = (source_id, touch_id);
+        if !self.active_touches.contains_key(&key) {
             // When a touch event is rejected, a TouchEnd is sent after it.
             return;
         }
 
 
-        self.handle_touch_update(touch_id, x, y);
+        self.handle_touch_update(touch_id, source_id, x, y);
 
         {
-            let touch = self.active_touches.get_mut(&touch_id).unwrap();
+            let touch = self.active_touches.get_mut(&key).unwrap();
             if !touch.is_decided {
                 self.current_is_ruined = true;
             }
@@ -157,15 +320,19 @@ impl <'a>GestureDetector<'a> {
             // The only touch in the vector is the touch that just ended.
             if self.current_direction != None && self.current_side != None && !self.current_is_ruined {
                 let on_gesture = self.on_gesture.take().unwrap();
-                let current_side = self.current_side.unwrap();
-                let current_direction = self.current_direction.unwrap();
-                let current_num_touches = self.current_num_touches;
+                let (start_x, start_y, device_id) = self.current_start.unwrap();
+                let gesture = Gesture {
+                    side: self.current_side.unwrap(),
+                    direction: self.current_direction.unwrap(),
+                    num_fingers: self.current_num_touches,
+                    start_x: start_x,
+                    start_y: start_y,
+                    end_x: x,
+                    end_y: y,
+                    device_id: device_id,
+                };
                 self.on_gesture = None;
-                (*on_gesture)(
-                    self,
-                    current_side,
-                    current_direction,
-                    current_num_touches);
+                (*on_gesture)(self, &gesture);
                 self.on_gesture = Some(on_gesture);
             }
             else {
@@ -175,12 +342,16 @@ impl <'a>GestureDetector<'a> {
             self.reset_state();
         }
         else {
-            self.active_touches.remove(&touch_id);
+            self.active_touches.remove(&key);
         }
     }
 
-    pub fn handle_touch_update(&mut self, touch_id: i32, x: f64, y: f64) {
-        if !self.active_touches.contains_key(&touch_id) {
+    /// Handles a moved touch. Once it has moved far enough, it gives the
+    /// gesture its direction and is accepted, or ruins the gesture if it moved
+    /// in another direction than the other touches.
+    pub fn handle_touch_update(&mut self, touch_id: i32, source_id: i32, x: f64, y: f64) {
+        let key = (source_id, touch_id);
+        if !self.active_touches.contains_key(&key) {
             // Do not reject it, otherwise BadValue crashes the system, in case
             // it was already accepted.
             //
@@ -190,7 +361,7 @@ impl <'a>GestureDetector<'a> {
         }
 
         {
-            let touch = self.active_touches.get_mut(&touch_id).unwrap();
+            let touch = self.active_touches.get_mut(&key).unwrap();
 
             match get_touch_direction(self.config, &touch, x, y) {
                 Some(ref direction) if self.current_direction == None => {
@@ -199,14 +370,15 @@ impl <'a>GestureDetector<'a> {
                     }
                     else if !touch.is_decided {
                         self.current_direction = Some(*direction);
+                        self.current_start = Some((touch.start_x, touch.start_y, touch.source_id));
                         self.current_num_touches += 1;
-                        (*self.on_accept_touch)(touch.touch_id, touch.device_id);
+                        (*self.on_accept_touch)(touch.touch_id, touch.device_id, touch.source_id);
                         touch.is_decided = true;
                     }
                 },
                 Some(ref direction)  if self.current_direction.unwrap() == *direction => {
                     if !touch.is_decided {
-                        (*self.on_accept_touch)(touch.touch_id, touch.device_id);
+                        (*self.on_accept_touch)(touch.touch_id, touch.device_id, touch.source_id);
                         self.current_num_touches += 1;
                         touch.is_decided = true;
                     }
@@ -225,9 +397,11 @@ impl <'a>GestureDetector<'a> {
     }
 
     fn reset_state(&mut self) {
+        self.last_is_ruined = self.current_is_ruined;
         self.active_touches.clear();
         self.current_side = None;
         self.current_direction = None;
+        self.current_start = None;
         self.current_num_touches = 0;
         self.current_is_ruined = false;
     }
@@ -258,11 +432,11 @@ fn get_touch_direction(config: &Config, touch: &Touch, end_x: f64, end_y: f64) -
     }
 }
 
-fn reject_touches(touches: &mut HashMap<i32, Touch>, on_reject_touch: &mut FnMut(i32, i32))
+fn reject_touches(touches: &mut HashMap<(i32, i32), Touch>, on_reject_touch: &mut dyn FnMut(i32, i32, i32))
 {
     for (_, touch) in touches {
         if !touch.is_decided {
-            on_reject_touch(touch.touch_id, touch.device_id);
+            on_reject_touch(touch.touch_id, touch.device_id, touch.source_id);
             touch.is_decided = true;
         }
     }
@@ -0,0 +1,325 @@
+//! Just enough JSON for writing events and status, one object per line, and
+//! for edgyctl to tell whether a response reports success.
+
+use std::fmt;
+use std::str;
+
+/// A JSON value, written on one line by Display.
+pub enum Json {
+    /// null
+    Null,
+    /// true or false
+    Bool(bool),
+    /// Integers are written without a fraction.
+    Number(f64),
+    /// A string, escaped when written.
+    String(String),
+    /// An array of values.
+    Array(Vec<Json>),
+    /// An object, with the keys in the given order.
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl<'a> From<&'a str> for Json {
+    fn from(value: &'a str) -> Json {
+        Json::String(String::from(value))
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Json {
+        Json::String(value)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Json {
+        Json::Bool(value)
+    }
+}
+
+impl From<u32> for Json {
+    fn from(value: u32) -> Json {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<i32> for Json {
+    fn from(value: i32) -> Json {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(value: f64) -> Json {
+        Json::Number(value)
+    }
+}
+
+impl<T> From<Option<T>> for Json where Json: From<T> {
+    fn from(value: Option<T>) -> Json {
+        value.map_or(Json::Null, Json::from)
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            // JSON has no infinities or NaN.
+            Json::Number(value) if !value.is_finite() => f.write_str("null"),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::String(ref value) => write_string(f, value),
+            Json::Array(ref values) => {
+                f.write_str("[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("]")
+            },
+            Json::Object(ref fields) => {
+                f.write_str("{")?;
+                for (i, &(key, ref value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_str("}")
+            },
+        }
+    }
+}
+
+// Reads through a line of JSON, for looking up fields without building values.
+struct Reader<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.position < self.input.len() && b" \t\r\n".contains(&self.input[self.position]) {
+            self.position += 1;
+        }
+    }
+
+    // Consumes the byte, after any whitespace, if it is next.
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        if self.input.get(self.position) == Some(&byte) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if !self.eat(b'"') {
+            return None;
+        }
+        let mut bytes = Vec::new();
+        loop {
+            let byte = *self.input.get(self.position)?;
+            self.position += 1;
+            let escaped = match byte {
+                b'"' => return String::from_utf8(bytes).ok(),
+                b'\\' => {
+                    let byte = *self.input.get(self.position)?;
+                    self.position += 1;
+                    match byte {
+                        b'"' | b'\\' | b'/' => byte as char,
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'u' => {
+                            let hex = self.input.get(self.position..self.position + 4)?;
+                            self.position += 4;
+                            let code = u32::from_str_radix(str::from_utf8(hex).ok()?, 16).ok()?;
+                            // Surrogate pairs are not needed for field names.
+                            char::from_u32(code).unwrap_or('\u{fffd}')
+                        },
+                        _ => return None,
+                    }
+                },
+                byte => {
+                    bytes.push(byte);
+                    continue;
+                },
+            };
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(escaped.encode_utf8(&mut buffer).as_bytes());
+        }
+    }
+
+    // A number, true, false or null.
+    fn literal(&mut self) -> Option<&'a [u8]> {
+        self.skip_whitespace();
+        let start = self.position;
+        while self.position < self.input.len() && !b",]} \t\r\n".contains(&self.input[self.position]) {
+            self.position += 1;
+        }
+        if self.position == start {
+            return None;
+        }
+        Some(&self.input[start..self.position])
+    }
+
+    // Calls the function with the name of each field of the object, which
+    // reads the value. Stops early when the function returns a result, and
+    // gives Ok(None) if the object ends without one. Malformed objects are
+    // errors.
+    fn object<T, F>(&mut self, mut field: F) -> Result<Option<T>, ()>
+        where F: FnMut(&mut Self, String) -> Result<Option<T>, ()>
+    {
+        if !self.eat(b'{') {
+            return Err(());
+        }
+        if self.eat(b'}') {
+            return Ok(None);
+        }
+        loop {
+            let name = self.string().ok_or(())?;
+            if !self.eat(b':') {
+                return Err(());
+            }
+            if let Some(result) = field(self, name)? {
+                return Ok(Some(result));
+            }
+            if self.eat(b'}') {
+                return Ok(None);
+            }
+            if !self.eat(b',') {
+                return Err(());
+            }
+        }
+    }
+
+    fn skip_value(&mut self) -> Result<(), ()> {
+        self.skip_whitespace();
+        match self.input.get(self.position) {
+            Some(&b'"') => self.string().map(|_| ()).ok_or(()),
+            Some(&b'{') => self.object(|reader, _| reader.skip_value().map(|_| None::<()>)).map(|_| ()),
+            Some(&b'[') => {
+                self.position += 1;
+                if self.eat(b']') {
+                    return Ok(());
+                }
+                loop {
+                    self.skip_value()?;
+                    if self.eat(b']') {
+                        return Ok(());
+                    }
+                    if !self.eat(b',') {
+                        return Err(());
+                    }
+                }
+            },
+            _ => self.literal().map(|_| ()).ok_or(()),
+        }
+    }
+}
+
+/// Returns the boolean field of the object on the line, None if the line is
+/// not an object, or the field is missing or not a boolean.
+pub fn bool_field(line: &str, name: &str) -> Option<bool> {
+    let mut reader = Reader { input: line.as_bytes(), position: 0 };
+    reader.object(|reader, field| {
+        if field != name {
+            return reader.skip_value().map(|_| None);
+        }
+        match reader.literal() {
+            Some(b"true") => Ok(Some(true)),
+            Some(b"false") => Ok(Some(false)),
+            _ => Err(()),
+        }
+    }).ok().and_then(|result| result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!(Json::from("say \"hi\"").to_string(), "\"say \\\"hi\\\"\"");
+        assert_eq!(Json::from("C:\\edgy\\").to_string(), "\"C:\\\\edgy\\\\\"");
+        assert_eq!(Json::from("a\nb\rc\td").to_string(), "\"a\\nb\\rc\\td\"");
+        assert_eq!(Json::from("\u{0}\u{1b}[1m\u{1f}").to_string(), "\"\\u0000\\u001b[1m\\u001f\"");
+        assert_eq!(Json::from("\u{7f} ").to_string(), "\"\u{7f} \"");
+    }
+
+    #[test]
+    fn non_ascii_is_written_as_is() {
+        assert_eq!(Json::from("Écran tactile ✋ 🖐").to_string(), "\"Écran tactile ✋ 🖐\"");
+    }
+
+    #[test]
+    fn keys_are_escaped_too() {
+        let object = Json::Object(vec![("a\"b", Json::Null)]);
+        assert_eq!(object.to_string(), "{\"a\\\"b\":null}");
+    }
+
+    #[test]
+    fn numbers_are_valid_json() {
+        assert_eq!(Json::from(3).to_string(), "3");
+        assert_eq!(Json::from(-1.5).to_string(), "-1.5");
+        assert_eq!(Json::from(1e21).to_string(), "1000000000000000000000");
+        assert_eq!(Json::from(f64::NAN).to_string(), "null");
+        assert_eq!(Json::from(f64::INFINITY).to_string(), "null");
+        assert_eq!(Json::from(f64::NEG_INFINITY).to_string(), "null");
+        let object = Json::Object(vec![("seconds", Json::from(f64::NAN)), ("x", Json::from(0.25))]);
+        assert_eq!(object.to_string(), "{\"seconds\":null,\"x\":0.25}");
+    }
+
+    #[test]
+    fn bool_field_is_found_at_the_top_level() {
+        assert_eq!(bool_field("{\"ok\":true}", "ok"), Some(true));
+        assert_eq!(bool_field(" { \"error\" : \"no\" , \"ok\" : false } ", "ok"), Some(false));
+        let status = "{\"paused\":false,\"devices\":[{\"ok\":false,\"name\":\"a \\\"}\\\" b\"}],\"layer\":null,\"ok\":true}";
+        assert_eq!(bool_field(status, "ok"), Some(true));
+    }
+
+    #[test]
+    fn bool_field_is_not_found_elsewhere() {
+        assert_eq!(bool_field("{\"event\":\"gesture\"}", "ok"), None);
+        assert_eq!(bool_field("{\"nested\":{\"ok\":true}}", "ok"), None);
+        assert_eq!(bool_field("{\"ok\":\"true\"}", "ok"), None);
+        assert_eq!(bool_field("{\"ok\":1}", "ok"), None);
+        assert_eq!(bool_field("[{\"ok\":true}]", "ok"), None);
+        assert_eq!(bool_field("{\"error\" \"ok\":true}", "ok"), None);
+        assert_eq!(bool_field("", "ok"), None);
+    }
+
+    #[test]
+    fn written_objects_are_read_back() {
+        let object = Json::Object(vec![
+            ("name", Json::from("\"ok\":false,\\")),
+            ("ok", Json::from(true)),
+        ]);
+        assert_eq!(bool_field(&object.to_string(), "ok"), Some(true));
+    }
+}
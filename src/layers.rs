@@ -0,0 +1,230 @@
+//! Layers are named sets of actions, of which one is active at a time. The
+//! active layer is either the base layer, or a temporary layer pushed on top
+//! of it. Temporary layers are left when a gesture that is not bound in them
+//! is made, which allows e.g. unlocking with a secret sequence of gestures.
+
+use config::Config;
+use devicegrab::*;
+use events;
+use json::Json;
+
+/// The layer of actions without "in layer", active at start unless another
+/// is given.
+pub const DEFAULT_LAYER: &str = "normal";
+
+/// The base layer and the temporary layers pushed on top of it.
+pub struct LayerStack {
+    base: String,
+    temporary: Vec<String>,
+    // Devices disabled because the current layer blocks touch. Only these are
+    // enabled again when leaving it, not the ones disabled by actions.
+    blocked_devices: Vec<i32>,
+}
+
+impl LayerStack {
+    /// Starts in the base layer, without temporary layers.
+    pub fn new(base: &str) -> LayerStack {
+        LayerStack {
+            base: String::from(base),
+            temporary: Vec::new(),
+            blocked_devices: Vec::new(),
+        }
+    }
+
+    /// Disables touch input of the devices if the current layer blocks touch,
+    /// e.g. for the initial layer or devices plugged in while in the layer.
+    pub fn block_touch(&mut self, config: &Config, device_ids: &[i32]) {
+        if !blocks_touch(config, self.current()) {
+            return;
+        }
+
+        let blocked: Vec<i32> = device_ids.iter()
+            .cloned()
+            .filter(|&device_id| !self.blocked_devices.contains(&device_id) && !is_touch_disabled(config, device_id))
+            .collect();
+        disable_touch(config, &blocked);
+        self.blocked_devices.extend(blocked);
+    }
+
+    /// The active layer, the topmost temporary layer or the base layer.
+    pub fn current(&self) -> &str {
+        self.temporary.last().unwrap_or(&self.base)
+    }
+
+    /// Returns whether a temporary layer is active.
+    pub fn is_temporary(&self) -> bool {
+        !self.temporary.is_empty()
+    }
+
+    /// Sets the base layer, leaving all temporary layers.
+    pub fn switch(&mut self, config: &Config, layer: &str) {
+        let old = self.current().to_string();
+        self.base = String::from(layer);
+        self.temporary.clear();
+        self.changed(config, &old);
+    }
+
+    /// Makes the layer active until it is popped or left.
+    pub fn push(&mut self, config: &Config, layer: &str) {
+        let old = self.current().to_string();
+        self.temporary.push(String::from(layer));
+        self.changed(config, &old);
+    }
+
+    /// Leaves the topmost temporary layer.
+    pub fn pop(&mut self, config: &Config) {
+        let old = self.current().to_string();
+        self.temporary.pop();
+        self.changed(config, &old);
+    }
+
+    /// Leaves all temporary layers, going back to the base layer.
+    pub fn pop_all(&mut self, config: &Config) {
+        let old = self.current().to_string();
+        self.temporary.clear();
+        self.changed(config, &old);
+    }
+
+    fn changed(&mut self, config: &Config, old: &str) {
+        let new = self.current().to_string();
+        if new == old {
+            return;
+        }
+
+        println!("Switched from layer {} to {}.", old, new);
+        events::emit("layer_changed", vec![("from", Json::from(old)), ("to", Json::from(new.as_str()))]);
+
+        if blocks_touch(config, &new) {
+            let device_ids = config.device_ids.borrow().clone();
+            self.block_touch(config, &device_ids);
+        }
+        else {
+            // Devices removed meanwhile are no longer disabled.
+            let device_ids = config.device_ids.borrow();
+            let blocked: Vec<i32> = self.blocked_devices.drain(..)
+                .filter(|device_id| device_ids.contains(device_id))
+                .collect();
+            enable_touch(config, &blocked);
+        }
+    }
+}
+
+/// Returns whether touch input is disabled for other applications while the
+/// layer is active.
+pub fn blocks_touch(config: &Config, layer: &str) -> bool {
+    config.touch_blocking_layers.iter().any(|blocking| blocking == layer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocking_config() -> Config {
+        let mut config = Config::fake(&[]);
+        config.touch_blocking_layers = vec![String::from("locked")];
+        config
+    }
+
+    #[test]
+    fn starts_in_the_base_layer() {
+        let layers = LayerStack::new("normal");
+        assert_eq!(layers.current(), "normal");
+        assert!(!layers.is_temporary());
+    }
+
+    #[test]
+    fn push_and_pop() {
+        let config = Config::fake(&[]);
+        let mut layers = LayerStack::new("normal");
+
+        layers.push(&config, "a");
+        layers.push(&config, "b");
+        assert_eq!(layers.current(), "b");
+        assert!(layers.is_temporary());
+
+        layers.pop(&config);
+        assert_eq!(layers.current(), "a");
+        assert!(layers.is_temporary());
+
+        layers.pop(&config);
+        assert_eq!(layers.current(), "normal");
+        assert!(!layers.is_temporary());
+
+        // The base layer is never popped.
+        layers.pop(&config);
+        assert_eq!(layers.current(), "normal");
+        assert!(!layers.is_temporary());
+    }
+
+    #[test]
+    fn pop_all_goes_back_to_the_base_layer() {
+        let config = Config::fake(&[]);
+        let mut layers = LayerStack::new("normal");
+        layers.push(&config, "a");
+        layers.push(&config, "b");
+
+        layers.pop_all(&config);
+        assert_eq!(layers.current(), "normal");
+        assert!(!layers.is_temporary());
+    }
+
+    #[test]
+    fn switch_replaces_the_base_layer_and_leaves_temporary_layers() {
+        let config = Config::fake(&[]);
+        let mut layers = LayerStack::new("normal");
+        layers.push(&config, "a");
+
+        layers.switch(&config, "presentation");
+        assert_eq!(layers.current(), "presentation");
+        assert!(!layers.is_temporary());
+
+        layers.push(&config, "a");
+        layers.pop(&config);
+        assert_eq!(layers.current(), "presentation");
+    }
+
+    #[test]
+    fn blocking_layers() {
+        let config = blocking_config();
+        assert!(blocks_touch(&config, "locked"));
+        assert!(!blocks_touch(&config, "normal"));
+        assert!(!blocks_touch(&Config::fake(&[]), "locked"));
+    }
+
+    #[test]
+    fn entering_a_blocking_layer_blocks_the_devices_until_it_is_left() {
+        let config = blocking_config();
+        let mut layers = LayerStack::new("normal");
+
+        layers.push(&config, "locked");
+        assert_eq!(layers.blocked_devices, vec![11]);
+
+        // Blocking layers on top of each other block the devices once.
+        layers.push(&config, "locked");
+        assert_eq!(layers.blocked_devices, vec![11]);
+        layers.pop(&config);
+        assert_eq!(layers.blocked_devices, vec![11]);
+
+        layers.pop(&config);
+        assert!(layers.blocked_devices.is_empty());
+    }
+
+    #[test]
+    fn devices_added_in_a_blocking_layer_are_blocked() {
+        let config = blocking_config();
+        let mut layers = LayerStack::new("normal");
+
+        layers.block_touch(&config, &[12]);
+        assert!(layers.blocked_devices.is_empty());
+
+        layers.switch(&config, "locked");
+        config.device_ids.borrow_mut().push(12);
+        layers.block_touch(&config, &[12]);
+        assert_eq!(layers.blocked_devices, vec![11, 12]);
+
+        // A device removed while blocked is forgotten when leaving the layer.
+        config.device_ids.borrow_mut().retain(|&device_id| device_id != 12);
+        layers.switch(&config, "normal");
+        assert!(layers.blocked_devices.is_empty());
+    }
+}
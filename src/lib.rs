@@ -0,0 +1,72 @@
+//! Edgy detects multi-touch swipes over screen edges and runs actions for
+//! them.
+//!
+//! The `edgy` binary is a command line wrapper around this library. Other
+//! programs can use the parts separately:
+//!
+//! * `gesture_detector`: `GestureDetector` turns touch begin / update / end
+//!   events into edge swipe `Gesture`s, and decides which touches to accept
+//!   or reject.
+//! * `config`: the `Config` the detector and actions work with.
+//! * `actions`: `parse_action` parses actions like
+//!   "from left to right with two fingers run 'xeyes'", and `find_actions`
+//!   selects the actions for a gesture.
+//! * `layers`: named sets of actions, of which one is active at a time.
+//! * `backend`: the `InputBackend` trait touches are read through.
+//!   `xinput_backend::XInputBackend` reads them from an XInput 2.2 touch grab,
+//!   `evdev_backend::EvdevBackend` reads them from /dev/input/event* without
+//!   X, and `scripted_backend::ScriptedBackend` replays a list of events, e.g.
+//!   for testing gesture detection without an X server.
+//! * `event_loop`: feeds the touches of a backend to the detector and runs
+//!   the actions.
+//! * `device_spec`: selecting devices by id, vendor and product, name or
+//!   pattern, and finding the touchscreens when none are given.
+//!   `device_list` describes the XInput devices for `edgy list-devices`.
+//! * `xconn`, `devicegrab` and `ewmh`: X11 helpers. Touchscreens are disabled
+//!   with device grabs or the "Device Enabled" property.
+//! * `events`, `json` and `control`: the JSON event stream and the control
+//!   socket used by `edgyctl`.
+//!
+//! A program using the X11 backend opens an `xconn::XConnection`, installs the
+//! handlers of `signals`, creates and grabs an `XInputBackend` with the
+//! connection, builds a `Config` holding it and runs
+//! `event_loop::run_event_loop`, which returns on SIGINT or SIGTERM after
+//! giving back the touchscreens. Without an event loop, events are passed
+//! to a `GestureDetector` with `backend::handle_event`.
+
+#![warn(missing_docs)]
+
+#[macro_use]
+extern crate nom;
+extern crate x11;
+extern crate libc;
+extern crate regex;
+
+pub mod gesture_detector;
+pub mod config;
+pub mod actions;
+pub mod layers;
+pub mod xconn;
+pub mod devicegrab;
+pub mod ewmh;
+pub mod backend;
+pub mod xinput_backend;
+pub mod scripted_backend;
+pub mod evdev_backend;
+pub mod event_loop;
+pub mod events;
+pub mod json;
+pub mod control;
+pub mod device_spec;
+pub mod device_list;
+pub mod signals;
+
+mod shell_words;
+mod supervisor;
+mod fake_input;
+mod overlay;
+mod dbus;
+mod notifications;
+mod dbus_service;
+mod evdev;
+mod uinput;
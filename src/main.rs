@@ -1,56 +1,50 @@
-mod gesture_detector;
-mod config;
-mod actions;
-mod xconn;
-mod devicegrab;
-
-#[macro_use]
-extern crate nom;
-extern crate x11;
+extern crate edgy;
 extern crate clap;
 
-use std::ffi::{
-    CString, 
-    CStr,
-};
-
-use std::mem::{zeroed, transmute};
-
-use std::vec::Vec;
-
-use std::os::raw::{c_int, c_uchar};
-use x11::{xlib, xinput2};
-
-use clap::{App, Arg};
-
-use config::Config;
-use gesture_detector::GestureDetector;
-use actions::parse_action;
-use xconn::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
+use std::time::Duration;
+
+use clap::{App, Arg, SubCommand};
+
+use edgy::actions::parse_action;
+use edgy::config::{Config, TouchDisableMethod};
+use edgy::backend::InputBackend;
+use edgy::device_list::describe_xinput_devices;
+use edgy::device_spec::{find_touchscreens, select_devices, DeviceSpec};
+use edgy::event_loop::run_event_loop;
+use edgy::evdev_backend::EvdevBackend;
+use edgy::xinput_backend::XInputBackend;
+use edgy::xconn::XConnection;
+use edgy::{control, events, layers, signals};
 
 fn main () {
 
-    initialize_x11();
-
-    if !has_xinput() {
-        panic!("XInput extension is not available.");
-    }
-
-    if !has_xinput_2_2() {
-        panic!("XInput extension is below XInput 2.2.");
-    }
-
-    grab_touch_begin();
-
     let matches = App::new("Edgy")
         .version("0.1.0")
         .author("Jasper Mattsson <jasu@njomotys.info>")
         .about("Launch commands on  multi-touch swipes over screen edges.")
         .arg(Arg::with_name("device-name")
              .short("d")
-             .help("Which named XInput device to use. For the list of available devices, run xinput --list.")
+             .help("Which XInput device to use: a part of its name, its id, its vendor and product id like 04f3:2234 or a /regular expression/ matching its name. By default all touchscreens are used, touchpads only when selected. For the list of available devices, run edgy list-devices.")
              .takes_value(true)
              .multiple(true))
+        .arg(Arg::with_name("evdev-device")
+             .short("e")
+             .help("Read touches from the touchscreen, e.g. /dev/input/event5, instead of XInput. Works without X.")
+             .takes_value(true)
+             .multiple(true))
+        .arg(Arg::with_name("pass-through")
+             .short("g")
+             .help("Grab the -e touchscreens, so that gestures do not reach other applications. Other touches are passed on through virtual touchscreens, which needs access to /dev/uinput.")
+             .requires("evdev-device"))
+        .arg(Arg::with_name("screen-size")
+             .long("screen-size")
+             .help("Size of the screen in pixels, e.g. 1920x1080. Required with -e when there is no X display.")
+             .takes_value(true))
         .arg(Arg::with_name("zone-width")
              .short("o")
              .help("Width, in pixels, of the region where a movement from outside to inside must start.")
@@ -63,23 +57,157 @@ fn main () {
              .short("t")
              .help("How many pixels the touch must move to the wrong direction before discarding it.")
              .takes_value(true))
+        .arg(Arg::with_name("command-timeout")
+             .short("k")
+             .help("Terminate commands that are still running after this many seconds.")
+             .takes_value(true))
+        .arg(Arg::with_name("initial-layer")
+             .short("i")
+             .help("Layer of actions to start in. Actions without \"in layer\" belong to the layer \"normal\".")
+             .takes_value(true))
+        .arg(Arg::with_name("block-touch-in-layer")
+             .short("b")
+             .help("Disable touch input for other applications while the layer is active.")
+             .takes_value(true)
+             .multiple(true))
+        .arg(Arg::with_name("use-device-enabled-property")
+             .short("p")
+             .help("Disable touch screens by setting their \"Device Enabled\" property instead of grabbing them. Gestures made on a disabled touch screen are not detected."))
+        .arg(Arg::with_name("show-overlay")
+             .short("f")
+             .help("Show the edge, direction and number of fingers while a gesture is being made."))
+        .arg(Arg::with_name("notify")
+             .short("n")
+             .help("Show a desktop notification when touch input is disabled or enabled."))
+        .arg(Arg::with_name("notify-failed-gestures")
+             .short("u")
+             .help("Show a desktop notification when a swipe is not recognized or has no action."))
+        .arg(Arg::with_name("dbus-service")
+             .short("s")
+             .help("Register info.njomotys.Edgy on the session bus, for observing gestures and controlling edgy."))
+        .arg(Arg::with_name("control-socket")
+             .short("c")
+             .help("Listen for edgyctl commands on the socket, $XDG_RUNTIME_DIR/edgy.sock if no path is given.")
+             .takes_value(true)
+             .min_values(0))
+        .arg(Arg::with_name("emit")
+             .long("emit")
+             .help("Print gestures, accepted and rejected touches and state changes to stdout, one JSON object per line. The log is written to stderr instead.")
+             .takes_value(true)
+             .possible_values(&["json"]))
         .arg(Arg::with_name("action")
              .short("a")
              .multiple(true)
              .help("E.g. \"from down to up with 2 fingers run command 'rm -rf/'\"")
              .takes_value(true))
+        .subcommand(SubCommand::with_name("list-devices")
+             .about("List the XInput devices with their touch capabilities, axes and outputs."))
         .get_matches();
 
-    let mut device_names = Vec::new();
-    for name in matches.values_of("device-name").unwrap() {
-        device_names.push(String::from(name));
+    if matches.subcommand_matches("list-devices").is_some() {
+        match XConnection::open() {
+            Ok(x11) => print!("{}", describe_xinput_devices(&x11)),
+            Err(error) => panic!("{}", error),
+        }
+        return;
+    }
+
+    if matches.value_of("emit") == Some("json") {
+        events::emit_to_stdout();
+    }
+
+    let evdev_paths: Option<Vec<PathBuf>> = matches.values_of("evdev-device")
+        .map(|paths| paths.map(PathBuf::from).collect());
+
+    // X is optional with evdev, window management and key actions then do
+    // nothing.
+    let x11 = match XConnection::open() {
+        Ok(x11) => Some(Rc::new(x11)),
+        Err(error) => {
+            if evdev_paths.is_none() {
+                panic!("{}", error);
+            }
+            println!("No X display, actions using X are not available.");
+            None
+        },
+    };
+
+    let (width, height) = match matches.value_of("screen-size") {
+        Some(size) => {
+            let mut parts = size.splitn(2, 'x').map(|part| part.parse::<u32>());
+            match (parts.next(), parts.next()) {
+                (Some(Ok(width)), Some(Ok(height))) => (width, height),
+                _ => panic!("Expected the screen size as WIDTHxHEIGHT."),
+            }
+        },
+        None => match x11 {
+            Some(ref x11) => x11.get_root_window_size().unwrap_or_else(|error| panic!("{}", error)),
+            None => panic!("No X display, give the screen size with --screen-size."),
+        },
+    };
+
+    let device_specs: Vec<DeviceSpec> = matches.values_of("device-name").into_iter().flatten()
+        .map(|spec| DeviceSpec::parse(spec).unwrap_or_else(|error| panic!("{}", error)))
+        .collect();
+
+    let (mut backend, devices): (Box<dyn InputBackend>, Vec<(i32, String)>) = match evdev_paths {
+        Some(ref paths) => {
+            let backend = match EvdevBackend::new(paths, width as f64, height as f64, matches.is_present("pass-through")) {
+                Ok(backend) => backend,
+                Err(error) => panic!("{}", error),
+            };
+            let devices = backend.devices();
+            (Box::new(backend), devices)
+        },
+        None => {
+            let x11 = x11.clone().unwrap();
+            let backend = match XInputBackend::new(x11.clone()) {
+                Ok(backend) => backend,
+                Err(error) => panic!("{}", error),
+            };
+
+            let available_devices = x11.list_xinput_devices();
+            let devices = if !device_specs.is_empty() {
+                let devices = select_devices(&device_specs, &available_devices);
+                if devices.is_empty() {
+                    panic!("Could not find any device that was requested.");
+                }
+                devices
+            }
+            else {
+                let devices = find_touchscreens(&available_devices);
+                if devices.is_empty() {
+                    panic!("Could not find a touchscreen, select devices with -d.");
+                }
+                for &(id, ref name) in &devices {
+                    println!("Using touchscreen {} (#{}).", name, id);
+                }
+                devices
+            };
+            (Box::new(backend), devices)
+        },
+    };
+    // Installed before grabbing, so that SIGINT or SIGTERM from then on stops
+    // the event loop, which gives the devices back, instead of killing edgy.
+    if let Err(error) = signals::install() {
+        println!("Could not install signal handlers: {}", error);
+    }
+    if let Err(error) = backend.grab() {
+        panic!("{}", error);
     }
 
-    let (width, height) = get_root_window_size();
+    if matches.is_present("show-overlay") && x11.is_none() {
+        panic!("The overlay needs an X display.");
+    }
 
     let mut actions = Vec::new();
 
-    for action_string in matches.values_of("action").unwrap() {
+    // Actions can be left out when only the events are wanted.
+    if !matches.is_present("action") && !matches.is_present("emit") {
+        panic!("No actions given.");
+    }
+
+    for action_string in matches.values_of("action").into_iter().flatten() {
         let s = action_string.to_string();
         match parse_action(s.as_bytes()) {
             Some(action) => actions.push(action),
@@ -88,6 +216,8 @@ fn main () {
     }
 
     let config = Config {
+        x11: x11,
+
         screen_width: width as f64,
         screen_height: height as f64,
 
@@ -106,254 +236,51 @@ fn main () {
             .parse::<f64>()
             .unwrap(),
 
-        device_ids: find_xinput_devices_by_name(device_names),
-
-        actions: actions,
-    };
-
-    run_event_loop(&config);
-}
-
+        device_specs: device_specs,
+        device_ids: RefCell::new(devices.iter().map(|&(id, _)| id).collect()),
+        device_names: RefCell::new(devices.into_iter().collect::<HashMap<_, _>>()),
 
-fn accept_touch(touch_id:i32, device_id: i32) {
-    if device_id != 2 {
-        // The event is from device grab, not touch grab, ignore it.
-        return;
-    }
-
-    unsafe { 
-        xinput2::XIAllowTouchEvents(display.unwrap(),
-                                    device_id,
-                                    touch_id as u32,
-                                    root_window,
-                                    xinput2::XIAcceptTouch);
-    }
-}
-
-fn reject_touch(touch_id:i32, device_id: i32) {
-    if device_id != 2 {
-        // The event is from device grab, not touch grab, ignore it.
-        return;
-    }
-
-    unsafe {
-        xinput2::XIAllowTouchEvents(display.unwrap(),
-                                    device_id,
-                                    touch_id as u32,
-                                    root_window,
-                                    xinput2::XIRejectTouch);
-    }
-}
-
-fn run_event_loop(config:&Config) {
-
-    let mut event: xlib::XEvent = unsafe { zeroed() };
-
-    let mut on_accept_touch = & mut |touch_id, device_id| {
-        accept_touch(touch_id, device_id);
-    };
-
-    let mut on_reject_touch = & mut |touch_id, device_id| {
-        reject_touch(touch_id, device_id);
-    };
-
-    let mut on_gesture = &mut |gd : &mut GestureDetector, side, direction, num_touches| {
-        for action in &config.actions {
-            if action.side == side && action.direction == direction && action.num_fingers == num_touches
-            {
-                (action.function)(gd);
-            }
-        }
-    };
-
-    let mut gesture_detector = GestureDetector::new(
-        &config,
-        on_accept_touch,
-        on_reject_touch,
-        on_gesture
-        );
-
-    loop {
-        unsafe { xlib::XNextEvent(display.unwrap(), &mut event) };
-        if event.get_type() != xlib::GenericEvent {
-            println!("Non-generic event.");
-            continue;
+        touch_disable_method: if evdev_paths.is_some() {
+            TouchDisableMethod::EvdevGrab
         }
-
-        // Logic: count touches that have crossed the same boundary.
-        // when all touches end, check to see if the counts match any action,
-        // then execute it. Reset counts.
-
-        let mut cookie:xlib::XGenericEventCookie = From::from(event);
-
-        if unsafe { xlib::XGetEventData(display.unwrap(), &mut cookie) } != xlib::True {
-            println!("XGetEventData failed.");
-            continue;
-        }
-
-        // Check if the state matches. Must have only touches for a single 
-        // direction, otherwise discard the events due to ambiguity.
-
-        match cookie.evtype {
-            xinput2::XI_TouchBegin => {
-                let event_data: &xinput2::XIDeviceEvent = unsafe{ transmute(cookie.data) };
-
-                // If the touch does not come from a device that is being listened to, discard it.
-                // (the root pointer device is grabbed since grabbing a single device does not work,
-                //  thus check sourceid, not deviceid)
-                let mut found = false;
-                for &device_id in &config.device_ids {
-                    if event_data.sourceid == device_id {
-                        found = true;
-                    }
-                }
-
-                if !found {
-                    reject_touch(event_data.detail, event_data.deviceid);
-                    continue;
-                }
-
-                gesture_detector.handle_touch_start(event_data.detail, event_data.deviceid, event_data.root_x, event_data.root_y);
-            },
-            xinput2::XI_TouchUpdate => {
-                let event_data: &xinput2::XIDeviceEvent = unsafe{ transmute(cookie.data) };
-                gesture_detector.handle_touch_update(event_data.detail, event_data.root_x, event_data.root_y);
-            },
-            xinput2::XI_TouchEnd => {
-                let event_data: &xinput2::XIDeviceEvent = unsafe{ transmute(cookie.data) };
-                gesture_detector.handle_touch_end(event_data.detail, event_data.root_x, event_data.root_y);
-            },
-            xinput2::XI_TouchOwnership => {
-            }
-            _ => { }
+        else if matches.is_present("use-device-enabled-property") {
+            TouchDisableMethod::DeviceEnabledProperty
         }
-    }
-}
-
-fn has_xinput() -> bool {
-    let mut opcode:c_int = 0;
-    let mut event:c_int = 0;
-    let mut error:c_int = 0;
-
-    let xinput_name = CString::new("XInputException").unwrap();
-
-    unsafe{
-        xlib::XQueryExtension(display.unwrap(), xinput_name.as_ptr(), &mut opcode, &mut event, &mut error) != xlib::True
-    }
-}
-
-fn has_xinput_2_2() -> bool {
-    let mut version_major = 2;
-    let mut version_minor = 2;
-
-    unsafe {
-        if xinput2::XIQueryVersion(display.unwrap(), &mut version_major, &mut version_minor) != xlib::Success as c_int 
-        {
-            return false;
-        }
-    }
-
-    version_major > 2 || version_major == 2 && version_minor >= 2
-}
-
-fn get_root_window_size() -> (u32, u32) {
-    let mut root:xlib::Window = 0;
-    let mut x = 0;
-    let mut y = 0;
-    let mut width = 0;
-    let mut height = 0;
-    let mut border_width = 0;
-    let mut depth = 0;
-    if unsafe { xlib::XGetGeometry(display.unwrap(),
-                                   root_window,
-                                   &mut root,
-                                   &mut x,
-                                   &mut y,
-                                   &mut width,
-                                   &mut height,
-                                   &mut border_width, &mut depth) } == xlib::False
-    {
-        panic!("Could not get window geometry.");
-    }
-
-    (width, height)
-}
+        else {
+            TouchDisableMethod::Grab
+        },
 
+        actions: actions,
 
+        show_overlay: matches.is_present("show-overlay"),
 
-fn for_each_xinput_device<F>(f: &mut F) -> ()
-    where F: FnMut(*mut xinput2::XIDeviceInfo) -> () 
-{
-    let mut device_count = 0;
-    unsafe {
-        let devices = xinput2::XIQueryDevice(display.unwrap(), xinput2::XIAllDevices, &mut device_count);
-        for i in 0..device_count {
-            let device = devices.offset(i as isize);
-            f(device);
-        }
-        xinput2::XIFreeDeviceInfo(devices);
-    }
-}
+        notify_touch_changes: matches.is_present("notify"),
+        notify_failed_gestures: matches.is_present("notify-failed-gestures"),
 
-fn find_xinput_devices_by_name(names:Vec<String>) -> Vec<c_int> {
-    let mut result:Vec<c_int> = Vec::new();
+        dbus_service: matches.is_present("dbus-service"),
 
-    for_each_xinput_device(&mut |device:*mut xinput2::XIDeviceInfo| {
-        unsafe {
-            let current_name = CStr::from_ptr((*device).name).to_string_lossy();
-            for name in &names {
-                if *name == current_name {
-                    result.push((*device).deviceid);
-                }
-            }
+        control_socket: if matches.is_present("control-socket") {
+            Some(matches.value_of("control-socket")
+                 .map(PathBuf::from)
+                 .unwrap_or_else(control::default_socket_path))
         }
-    });
+        else {
+            None
+        },
 
-    if result.len() == 0 {
-        panic!("Could not find any device that was requested.");
-    }
-    if names.len() != result.len() {
-        println!("Warning: found {} devices out of {} requested.",
-                 result.len(),
-                 names.len());
-    }
-
-    result
-}
+        command_timeout: matches.value_of("command-timeout")
+            .map(|seconds| Duration::from_secs(seconds.parse::<u64>().unwrap())),
 
-pub fn grab_touch_begin()
-{
-    // The pointer root device (id=2) is grabbed here. Grabbing a single device
-    // does not seem to work (events are received AFTER they are processed by 
-    // other applications, even for grabs.)
+        initial_layer: String::from(matches.value_of("initial-layer").unwrap_or(layers::DEFAULT_LAYER)),
 
-    let mut mask: [c_uchar; 4] = [0; 4];
-    let mut modifiers =  xinput2::XIGrabModifiers {
-        modifiers: 1 << 31,
-        status: 0,
-    };
-    let mut input_event_mask = xinput2::XIEventMask {
-        deviceid: 2, //xinput2::XIAllDevices,
-        mask_len: mask.len() as i32,
-        mask: mask.as_mut_ptr(),
+        touch_blocking_layers: matches.values_of("block-touch-in-layer")
+            .map(|layers| layers.map(String::from).collect())
+            .unwrap_or_default(),
     };
 
-    for &event in &[xinput2::XI_TouchBegin,
-                    xinput2::XI_TouchUpdate,
-                    xinput2::XI_TouchEnd,
-                    xinput2::XI_TouchOwnership, ]
-    {
-        xinput2::XISetMask(&mut mask, event);
-    }
+    let signal = run_event_loop(&config, backend);
 
-    if unsafe { xinput2::XIGrabTouchBegin(display.unwrap(),
-                                          2,
-                                          root_window,
-                                          /*owner_events=*/xlib::False,
-                                          &mut input_event_mask,
-                                          /*num_modifiers=*/1,
-                                          &mut modifiers) } != 0
-    {
-        panic!("Could not grab TouchBegin.");
-    }
+    // Closes the X connection, process::exit does not run destructors.
+    drop(config);
+    process::exit(128 + signal);
 }
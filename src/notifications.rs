@@ -0,0 +1,88 @@
+// Desktop notifications through the org.freedesktop.Notifications D-Bus
+// interface of the session bus.
+// See https://specifications.freedesktop.org/notification-spec/latest/
+
+use std::cell::RefCell;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use dbus::{Connection, Value};
+
+const DESTINATION: &str = "org.freedesktop.Notifications";
+const PATH: &str = "/org/freedesktop/Notifications";
+const INTERFACE: &str = "org.freedesktop.Notifications";
+
+const APP_NAME: &str = "Edgy";
+const ICON: &str = "input-touchscreen";
+
+// Let the notification server decide how long notifications are shown.
+const DEFAULT_EXPIRE_TIMEOUT: i32 = -1;
+
+thread_local! {
+    // Notifications are sent by a thread of their own, so that a slow or
+    // missing notification server does not hold up the event loop.
+    static SENDER: RefCell<Option<Sender<(String, String)>>> = const { RefCell::new(None) };
+}
+
+fn send_notification(connection: &mut Connection, replaces_id: u32, summary: &str, body: &str)
+    -> Result<u32, String>
+{
+    let reply = connection.call(DESTINATION, PATH, INTERFACE, "Notify", vec![
+        Value::String(String::from(APP_NAME)),
+        Value::UInt32(replaces_id),
+        Value::String(String::from(ICON)),
+        Value::String(String::from(summary)),
+        Value::String(String::from(body)),
+        Value::Array(String::from("s"), vec![]),
+        Value::Array(String::from("{sv}"), vec![]),
+        Value::Int32(DEFAULT_EXPIRE_TIMEOUT),
+    ])?;
+    Ok(reply.first().and_then(Value::as_u32).unwrap_or(0))
+}
+
+fn send_notifications(receiver: Receiver<(String, String)>) {
+    // Connected on first use, and again after an error.
+    let mut connection: Option<Connection> = None;
+    // The previous notification is replaced, so that they do not pile up.
+    let mut last_id = 0;
+
+    for (summary, body) in receiver {
+        if connection.is_none() {
+            match Connection::session() {
+                Ok(new_connection) => connection = Some(new_connection),
+                Err(error) => {
+                    println!("Could not connect to the session bus: {}", error);
+                    continue;
+                },
+            }
+        }
+
+        match send_notification(connection.as_mut().unwrap(), last_id, &summary, &body) {
+            Ok(id) => last_id = id,
+            Err(error) => {
+                println!("Could not show notification: {}", error);
+                connection = None;
+            },
+        }
+    }
+}
+
+// Shows a notification, without waiting for it to be shown.
+pub fn notify(summary: &str, body: &str) {
+    SENDER.with(|sender| {
+        let mut sender = sender.borrow_mut();
+        if sender.is_none() {
+            let (new_sender, receiver) = channel();
+            let spawned = thread::Builder::new()
+                .name(String::from("notifications"))
+                .spawn(move || send_notifications(receiver));
+            if let Err(error) = spawned {
+                println!("Could not start the notification thread: {}", error);
+                return;
+            }
+            *sender = Some(new_sender);
+        }
+
+        let _ = sender.as_ref().unwrap().send((String::from(summary), String::from(body)));
+    });
+}
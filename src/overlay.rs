@@ -0,0 +1,350 @@
+// Shows feedback while a gesture is being made: the edge zone the gesture
+// started in, as a bar along the screen edge and an outline of the rest of the
+// zone, and a label with the direction and number of fingers. Both turn red
+// when the gesture is ruined.
+//
+// The windows are override-redirect, so that the window manager leaves them
+// alone, and have an empty input shape, so that they do not take input. The
+// label is drawn again when exposed, the bar only has its background.
+
+use std::ffi::CString;
+use std::mem::zeroed;
+use std::os::raw::{c_int, c_long, c_uint, c_ulong};
+use std::ptr::null_mut;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use x11::xlib;
+
+use config::Config;
+use gesture_detector::{Direction, GestureDetector, GestureProgress, Side};
+use xconn::XConnection;
+
+const BAR_THICKNESS: i32 = 8;
+const OUTLINE_THICKNESS: i32 = 2;
+const LABEL_WIDTH: i32 = 140;
+const LABEL_HEIGHT: i32 = 48;
+const LABEL_MARGIN: i32 = 16;
+
+// How long a ruined gesture is shown after its touches have ended.
+const RUINED_FLASH_MS: u64 = 400;
+
+const ACTIVE_COLOR: &str = "SteelBlue";
+const RUINED_COLOR: &str = "red3";
+const TEXT_COLOR: &str = "white";
+
+const LABEL_FONT: &str = "-misc-fixed-bold-r-normal--18-*-*-*-*-*-iso8859-1";
+
+// From X11/extensions/shape.h, the x11 crate has no bindings for them.
+const SHAPE_SET: c_int = 0;
+const SHAPE_BOUNDING: c_int = 0;
+const SHAPE_INPUT: c_int = 2;
+const UNSORTED: c_int = 0;
+
+#[link(name = "Xext")]
+extern "C" {
+    fn XShapeCombineRectangles(display: *mut xlib::Display,
+                               window: xlib::Window,
+                               dest_kind: c_int,
+                               x_offset: c_int,
+                               y_offset: c_int,
+                               rectangles: *mut xlib::XRectangle,
+                               num_rectangles: c_int,
+                               op: c_int,
+                               ordering: c_int);
+}
+
+pub struct Overlay {
+    screen_width: i32,
+    screen_height: i32,
+    zone_width: i32,
+
+    x11: Rc<XConnection>,
+    bar: xlib::Window,
+    label: xlib::Window,
+    gc: xlib::GC,
+    font_ascent: i32,
+
+    active_pixel: c_ulong,
+    ruined_pixel: c_ulong,
+    text_pixel: c_ulong,
+
+    // What is currently shown, if anything.
+    shown: Option<GestureProgress>,
+    // When to hide a ruined gesture that has ended.
+    hide_at: Option<Instant>,
+}
+
+fn alloc_color(x11: &XConnection, name: &str, fallback: c_ulong) -> c_ulong {
+    let c_name = CString::new(name).unwrap();
+    unsafe {
+        let mut screen_color: xlib::XColor = zeroed();
+        let mut exact_color: xlib::XColor = zeroed();
+        let colormap = xlib::XDefaultColormap(x11.display(), xlib::XDefaultScreen(x11.display()));
+        if xlib::XAllocNamedColor(x11.display(), colormap, c_name.as_ptr(), &mut screen_color, &mut exact_color) != 0 {
+            screen_color.pixel
+        }
+        else {
+            println!("Could not allocate color {}.", name);
+            fallback
+        }
+    }
+}
+
+fn create_window(x11: &XConnection, background: c_ulong, event_mask: c_long) -> xlib::Window {
+    unsafe {
+        let mut attributes: xlib::XSetWindowAttributes = zeroed();
+        attributes.override_redirect = xlib::True;
+        attributes.background_pixel = background;
+        attributes.save_under = xlib::True;
+        attributes.backing_store = xlib::Always;
+        attributes.event_mask = event_mask;
+
+        let window = xlib::XCreateWindow(x11.display(),
+                                         x11.root_window(),
+                                         0, 0, 1, 1,
+                                         /*border_width=*/0,
+                                         xlib::CopyFromParent,
+                                         xlib::InputOutput as c_uint,
+                                         /*visual=*/null_mut(),
+                                         xlib::CWOverrideRedirect | xlib::CWBackPixel | xlib::CWSaveUnder | xlib::CWBackingStore |
+                                         xlib::CWEventMask,
+                                         &mut attributes);
+
+        XShapeCombineRectangles(x11.display(), window, SHAPE_INPUT, 0, 0, null_mut(), 0, SHAPE_SET, UNSORTED);
+        window
+    }
+}
+
+// Arrow pointing right, relative to its center.
+const ARROW: [(i32, i32); 7] = [(-16, -6), (0, -6), (0, -14), (16, 0), (0, 14), (0, 6), (-16, 6)];
+
+fn arrow_points(direction: Direction, center_x: i32, center_y: i32) -> Vec<xlib::XPoint> {
+    ARROW.iter()
+        .map(|&(x, y)| match direction {
+            Direction::Right => (x, y),
+            Direction::Down => (-y, x),
+            Direction::Left => (-x, -y),
+            Direction::Up => (y, -x),
+        })
+        .map(|(x, y)| xlib::XPoint { x: (center_x + x) as i16, y: (center_y + y) as i16 })
+        .collect()
+}
+
+impl Overlay {
+    pub fn new(x11: Rc<XConnection>, config: &Config) -> Overlay {
+        let (white, black) = unsafe {
+            let screen = xlib::XDefaultScreen(x11.display());
+            (xlib::XWhitePixel(x11.display(), screen), xlib::XBlackPixel(x11.display(), screen))
+        };
+
+        let active_pixel = alloc_color(&x11, ACTIVE_COLOR, black);
+        let ruined_pixel = alloc_color(&x11, RUINED_COLOR, black);
+        let text_pixel = alloc_color(&x11, TEXT_COLOR, white);
+
+        let bar = create_window(&x11, active_pixel, xlib::NoEventMask);
+        let label = create_window(&x11, active_pixel, xlib::ExposureMask);
+
+        let (gc, font_ascent) = unsafe {
+            let gc = xlib::XCreateGC(x11.display(), label, 0, null_mut());
+            let font_name = CString::new(LABEL_FONT).unwrap();
+            let font = xlib::XLoadQueryFont(x11.display(), font_name.as_ptr());
+            let ascent = if font.is_null() {
+                println!("Could not load font {}, using the default font.", LABEL_FONT);
+                10
+            }
+            else {
+                xlib::XSetFont(x11.display(), gc, (*font).fid);
+                let ascent = (*font).ascent;
+                xlib::XFreeFontInfo(null_mut(), font, 1);
+                ascent
+            };
+            (gc, ascent)
+        };
+
+        Overlay {
+            screen_width: config.screen_width as i32,
+            screen_height: config.screen_height as i32,
+            zone_width: config.zone_width.round() as i32,
+            x11: x11,
+            bar: bar,
+            label: label,
+            gc: gc,
+            font_ascent: font_ascent,
+            active_pixel: active_pixel,
+            ruined_pixel: ruined_pixel,
+            text_pixel: text_pixel,
+            shown: None,
+            hide_at: None,
+        }
+    }
+
+    // Shows the progress of the current gesture, to be called after each
+    // touch event.
+    pub fn update(&mut self, gesture_detector: &GestureDetector) {
+        match gesture_detector.progress() {
+            // Taps near the edge are not shown, only swipes.
+            Some(ref progress) if progress.direction.is_none() => {},
+            Some(progress) => {
+                self.hide_at = None;
+                if self.shown != Some(progress) {
+                    self.show(progress);
+                }
+            },
+            None => {
+                if self.hide_at.is_some() {
+                    return;
+                }
+                match self.shown {
+                    Some(shown) if gesture_detector.last_gesture_was_ruined() => {
+                        // Keep the ruined gesture visible for a moment.
+                        if !shown.is_ruined {
+                            self.show(GestureProgress { is_ruined: true, ..shown });
+                        }
+                        self.hide_at = Some(Instant::now() + Duration::from_millis(RUINED_FLASH_MS));
+                    },
+                    Some(_) => self.hide(),
+                    None => {},
+                }
+            },
+        }
+    }
+
+    // How long the event loop may wait before calling handle_timeout().
+    pub fn timeout(&self) -> Option<Duration> {
+        self.hide_at.map(|hide_at| {
+            let now = Instant::now();
+            if hide_at > now { hide_at - now } else { Duration::from_millis(0) }
+        })
+    }
+
+    pub fn handle_timeout(&mut self) {
+        if let Some(hide_at) = self.hide_at {
+            if Instant::now() >= hide_at {
+                self.hide();
+            }
+        }
+    }
+
+    // Draws the label again if it was exposed, to be called after the events
+    // of the X connection have been read.
+    pub fn handle_events(&mut self) {
+        let mut events = self.x11.take_deferred_events();
+        unsafe {
+            let mut event: xlib::XEvent = zeroed();
+            while xlib::XCheckWindowEvent(self.x11.display(), self.label, xlib::ExposureMask, &mut event) == xlib::True {
+                events.push(event);
+            }
+        }
+
+        let exposed = events.iter()
+            .any(|&event| event.get_type() == xlib::Expose && xlib::XExposeEvent::from(event).window == self.label);
+        if let (true, Some(progress)) = (exposed, self.shown) {
+            self.draw_label(progress);
+            unsafe { xlib::XFlush(self.x11.display()); }
+        }
+    }
+
+    fn show(&mut self, progress: GestureProgress) {
+        let (width, height) = (self.screen_width, self.screen_height);
+        let zone = match progress.side {
+            Side::Top | Side::Bottom => self.zone_width.max(BAR_THICKNESS).min(height),
+            Side::Left | Side::Right => self.zone_width.max(BAR_THICKNESS).min(width),
+        };
+        let (zone_x, zone_y, zone_width, zone_height) = match progress.side {
+            Side::Top => (0, 0, width, zone),
+            Side::Bottom => (0, height - zone, width, zone),
+            Side::Left => (0, 0, zone, height),
+            Side::Right => (width - zone, 0, zone, height),
+        };
+
+        // The zone window only shows the bar on the edge and the outline, the
+        // rest of it is cut away.
+        let bar = match progress.side {
+            Side::Top => (0, 0, zone_width, BAR_THICKNESS),
+            Side::Bottom => (0, zone_height - BAR_THICKNESS, zone_width, BAR_THICKNESS),
+            Side::Left => (0, 0, BAR_THICKNESS, zone_height),
+            Side::Right => (zone_width - BAR_THICKNESS, 0, BAR_THICKNESS, zone_height),
+        };
+        let mut shape: Vec<xlib::XRectangle> = [
+                bar,
+                (0, 0, zone_width, OUTLINE_THICKNESS),
+                (0, zone_height - OUTLINE_THICKNESS, zone_width, OUTLINE_THICKNESS),
+                (0, 0, OUTLINE_THICKNESS, zone_height),
+                (zone_width - OUTLINE_THICKNESS, 0, OUTLINE_THICKNESS, zone_height),
+            ].iter()
+            .map(|&(x, y, width, height)| xlib::XRectangle {
+                x: x as i16, y: y as i16, width: width as u16, height: height as u16,
+            })
+            .collect();
+
+        let offset = zone + LABEL_MARGIN;
+        let (label_x, label_y) = match progress.side {
+            Side::Top => ((width - LABEL_WIDTH) / 2, offset),
+            Side::Bottom => ((width - LABEL_WIDTH) / 2, height - offset - LABEL_HEIGHT),
+            Side::Left => (offset, (height - LABEL_HEIGHT) / 2),
+            Side::Right => (width - offset - LABEL_WIDTH, (height - LABEL_HEIGHT) / 2),
+        };
+
+        let background = if progress.is_ruined { self.ruined_pixel } else { self.active_pixel };
+
+        unsafe {
+            let d = self.x11.display();
+
+            xlib::XSetWindowBackground(d, self.bar, background);
+            xlib::XMoveResizeWindow(d, self.bar, zone_x, zone_y, zone_width as c_uint, zone_height as c_uint);
+            XShapeCombineRectangles(d, self.bar, SHAPE_BOUNDING, 0, 0, shape.as_mut_ptr(), shape.len() as c_int,
+                                    SHAPE_SET, UNSORTED);
+            xlib::XMapRaised(d, self.bar);
+            xlib::XClearWindow(d, self.bar);
+
+            xlib::XSetWindowBackground(d, self.label, background);
+            xlib::XMoveResizeWindow(d, self.label, label_x, label_y, LABEL_WIDTH as c_uint, LABEL_HEIGHT as c_uint);
+            xlib::XMapRaised(d, self.label);
+        }
+        self.draw_label(progress);
+        unsafe { xlib::XFlush(self.x11.display()); }
+
+        self.shown = Some(progress);
+    }
+
+    // Draws the arrow and the number of fingers on the label.
+    fn draw_label(&self, progress: GestureProgress) {
+        let text = format!("{} finger{}", progress.num_fingers, if progress.num_fingers == 1 { "" } else { "s" });
+        unsafe {
+            let d = self.x11.display();
+            xlib::XClearWindow(d, self.label);
+
+            xlib::XSetForeground(d, self.gc, self.text_pixel);
+            if let Some(direction) = progress.direction {
+                let mut points = arrow_points(direction, LABEL_HEIGHT / 2, LABEL_HEIGHT / 2);
+                xlib::XFillPolygon(d, self.label, self.gc, points.as_mut_ptr(), points.len() as c_int,
+                                   xlib::Nonconvex, xlib::CoordModeOrigin);
+            }
+            let c_text = CString::new(text).unwrap();
+            xlib::XDrawString(d, self.label, self.gc,
+                              LABEL_HEIGHT, (LABEL_HEIGHT + self.font_ascent) / 2,
+                              c_text.as_ptr(), c_text.as_bytes().len() as c_int);
+        }
+    }
+
+    fn hide(&mut self) {
+        unsafe {
+            xlib::XUnmapWindow(self.x11.display(), self.bar);
+            xlib::XUnmapWindow(self.x11.display(), self.label);
+            xlib::XFlush(self.x11.display());
+        }
+        self.shown = None;
+        self.hide_at = None;
+    }
+}
+
+impl Drop for Overlay {
+    fn drop(&mut self) {
+        unsafe {
+            xlib::XFreeGC(self.x11.display(), self.gc);
+            xlib::XDestroyWindow(self.x11.display(), self.label);
+            xlib::XDestroyWindow(self.x11.display(), self.bar);
+            xlib::XFlush(self.x11.display());
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! A backend that replays a list of events instead of reading a device, for
+//! running the gesture detection without an X server, e.g. in tests.
+
+use std::collections::VecDeque;
+use std::os::raw::c_int;
+use std::time::Duration;
+
+use backend::{poll, InputBackend, TouchEvent};
+
+/// Replays its events, and records what is done with the touches.
+#[derive(Default)]
+pub struct ScriptedBackend {
+    events: VecDeque<TouchEvent>,
+    /// Whether the backend is grabbed.
+    pub grabbed: bool,
+    /// Touches in the order they were accepted, as (touch_id, device_id).
+    pub accepted: Vec<(i32, i32)>,
+    /// Touches in the order they were rejected, as (touch_id, device_id).
+    pub rejected: Vec<(i32, i32)>,
+}
+
+impl ScriptedBackend {
+    /// Replays the events in order.
+    pub fn new(events: Vec<TouchEvent>) -> ScriptedBackend {
+        ScriptedBackend {
+            events: events.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Adds events after the ones not replayed yet.
+    pub fn push(&mut self, event: TouchEvent) {
+        self.events.push_back(event);
+    }
+
+    /// Adds a touch moving in a straight line from (x, y) to (end_x, end_y).
+    pub fn push_swipe(&mut self, touch_id: i32, device_id: i32, (x, y): (f64, f64), (end_x, end_y): (f64, f64)) {
+        self.push(TouchEvent::Begin { touch_id: touch_id, device_id: device_id, source_id: device_id, x: x, y: y });
+        self.push(TouchEvent::Update { touch_id: touch_id, source_id: device_id, x: (x + end_x) / 2.0, y: (y + end_y) / 2.0 });
+        self.push(TouchEvent::End { touch_id: touch_id, source_id: device_id, x: end_x, y: end_y });
+    }
+
+    /// Returns whether all events were replayed.
+    pub fn is_finished(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+impl InputBackend for ScriptedBackend {
+    fn grab(&mut self) -> Result<(), String> {
+        self.grabbed = true;
+        Ok(())
+    }
+
+    fn ungrab(&mut self) {
+        self.grabbed = false;
+    }
+
+    fn wait(&mut self, timeout: Option<Duration>, other_fds: &[c_int]) -> bool {
+        if !self.events.is_empty() {
+            return true;
+        }
+        // Out of events, behave like a device nobody touches.
+        poll(other_fds, timeout);
+        false
+    }
+
+    fn next_event(&mut self) -> Option<TouchEvent> {
+        self.events.pop_front()
+    }
+
+    fn reject_touch(&mut self, touch_id: i32, device_id: i32) {
+        self.rejected.push((touch_id, device_id));
+    }
+
+    fn accept_touch(&mut self, touch_id: i32, device_id: i32) {
+        self.accepted.push((touch_id, device_id));
+    }
+
+    fn devices_changed(&mut self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    use actions::find_actions;
+    use backend::handle_event;
+    use config::Config;
+    use gesture_detector::{Gesture, GestureDetector};
+
+    const DEVICE: i32 = 11;
+    const OTHER_DEVICE: i32 = 12;
+
+    // Replays the events like the event loop does, returning the backend and
+    // the descriptions of the actions matched by the gestures.
+    fn run(config: &Config, backend: ScriptedBackend) -> (ScriptedBackend, Vec<String>) {
+        let backend = RefCell::new(backend);
+        let matched = RefCell::new(Vec::new());
+        {
+            let mut on_accept_touch = |touch_id, device_id, _| backend.borrow_mut().accept_touch(touch_id, device_id);
+            let mut on_reject_touch = |touch_id, device_id, _| backend.borrow_mut().reject_touch(touch_id, device_id);
+            let mut on_gesture = |gd: &mut GestureDetector, gesture: &Gesture| {
+                for action in find_actions(&config.actions, gd.layers.current(), gesture, None) {
+                    matched.borrow_mut().push(action.description.clone());
+                }
+            };
+            let mut gesture_detector = GestureDetector::new(config, &mut on_accept_touch, &mut on_reject_touch,
+                                                            &mut on_gesture);
+
+            loop {
+                let event = match backend.borrow_mut().next_event() {
+                    Some(event) => event,
+                    None => break,
+                };
+                if !handle_event(&mut gesture_detector, event) {
+                    if let TouchEvent::Begin { touch_id, device_id, .. } = event {
+                        backend.borrow_mut().reject_touch(touch_id, device_id);
+                    }
+                }
+            }
+        }
+        (backend.into_inner(), matched.into_inner())
+    }
+
+    fn begin(touch_id: i32, device_id: i32, x: f64, y: f64) -> TouchEvent {
+        TouchEvent::Begin { touch_id: touch_id, device_id: device_id, source_id: device_id, x: x, y: y }
+    }
+
+    fn update(touch_id: i32, device_id: i32, x: f64, y: f64) -> TouchEvent {
+        TouchEvent::Update { touch_id: touch_id, source_id: device_id, x: x, y: y }
+    }
+
+    fn end(touch_id: i32, device_id: i32, x: f64, y: f64) -> TouchEvent {
+        TouchEvent::End { touch_id: touch_id, source_id: device_id, x: x, y: y }
+    }
+
+    const ACTIONS: &[&str] = &[
+        "from left to right with one finger run 'one'",
+        "from left to right with two fingers run 'two'",
+        "from top to bottom with one finger run 'down'",
+        "from left to right with one finger run 'locked' in layer 'locked'",
+    ];
+
+    #[test]
+    fn one_finger_swipe_runs_its_action() {
+        let config = Config::fake(ACTIONS);
+        let mut backend = ScriptedBackend::new(vec![]);
+        backend.push_swipe(1, DEVICE, (5.0, 400.0), (300.0, 400.0));
+
+        let (backend, matched) = run(&config, backend);
+        assert_eq!(matched, vec!["from left to right with one finger run 'one'"]);
+        assert_eq!(backend.accepted, vec![(1, DEVICE)]);
+        assert!(backend.rejected.is_empty());
+        assert!(backend.is_finished());
+    }
+
+    #[test]
+    fn two_finger_swipe_accepts_both_touches() {
+        let config = Config::fake(ACTIONS);
+        let backend = ScriptedBackend::new(vec![
+            begin(1, DEVICE, 5.0, 300.0),
+            begin(2, DEVICE, 10.0, 500.0),
+            update(1, DEVICE, 150.0, 300.0),
+            update(2, DEVICE, 160.0, 500.0),
+            end(1, DEVICE, 300.0, 300.0),
+            end(2, DEVICE, 310.0, 500.0),
+        ]);
+
+        let (backend, matched) = run(&config, backend);
+        assert_eq!(matched, vec!["from left to right with two fingers run 'two'"]);
+        assert_eq!(backend.accepted, vec![(1, DEVICE), (2, DEVICE)]);
+        assert!(backend.rejected.is_empty());
+    }
+
+    #[test]
+    fn touch_outside_the_edges_is_rejected() {
+        let config = Config::fake(ACTIONS);
+        let mut backend = ScriptedBackend::new(vec![]);
+        backend.push_swipe(1, DEVICE, (500.0, 400.0), (800.0, 400.0));
+
+        let (backend, matched) = run(&config, backend);
+        assert!(matched.is_empty());
+        assert!(backend.accepted.is_empty());
+        assert_eq!(backend.rejected, vec![(1, DEVICE)]);
+    }
+
+    #[test]
+    fn swipe_along_the_edge_is_rejected() {
+        let config = Config::fake(ACTIONS);
+        let mut backend = ScriptedBackend::new(vec![]);
+        backend.push_swipe(1, DEVICE, (5.0, 600.0), (5.0, 100.0));
+
+        let (backend, matched) = run(&config, backend);
+        assert!(matched.is_empty());
+        assert!(backend.accepted.is_empty());
+        assert_eq!(backend.rejected, vec![(1, DEVICE)]);
+    }
+
+    #[test]
+    fn touch_on_another_edge_ruins_the_gesture() {
+        let config = Config::fake(ACTIONS);
+        let backend = ScriptedBackend::new(vec![
+            begin(1, DEVICE, 5.0, 400.0),
+            update(1, DEVICE, 150.0, 400.0),
+            begin(2, DEVICE, 500.0, 5.0),
+            update(2, DEVICE, 500.0, 150.0),
+            end(2, DEVICE, 500.0, 300.0),
+            end(1, DEVICE, 300.0, 400.0),
+        ]);
+
+        let (backend, matched) = run(&config, backend);
+        assert!(matched.is_empty());
+        // The first touch was accepted before the gesture was ruined.
+        assert_eq!(backend.accepted, vec![(1, DEVICE)]);
+        assert_eq!(backend.rejected, vec![(2, DEVICE)]);
+    }
+
+    #[test]
+    fn touches_of_other_devices_are_rejected() {
+        let config = Config::fake(ACTIONS);
+        let backend = ScriptedBackend::new(vec![
+            begin(1, DEVICE, 400.0, 5.0),
+            begin(2, OTHER_DEVICE, 5.0, 400.0),
+            update(1, DEVICE, 400.0, 150.0),
+            update(2, OTHER_DEVICE, 150.0, 400.0),
+            end(2, OTHER_DEVICE, 300.0, 400.0),
+            end(1, DEVICE, 400.0, 300.0),
+        ]);
+
+        let (backend, matched) = run(&config, backend);
+        // The touch of the other device neither makes a gesture nor ruins
+        // the one being made.
+        assert_eq!(matched, vec!["from top to bottom with one finger run 'down'"]);
+        assert_eq!(backend.accepted, vec![(1, DEVICE)]);
+        assert_eq!(backend.rejected, vec![(2, OTHER_DEVICE)]);
+    }
+
+    #[test]
+    fn touches_are_told_apart_by_device() {
+        let config = Config::fake(ACTIONS);
+        *config.device_ids.borrow_mut() = vec![DEVICE, OTHER_DEVICE];
+        // Touch ids are only unique per device, e.g. a touchscreen and a
+        // touchpad can both have a touch 1.
+        let backend = ScriptedBackend::new(vec![
+            begin(1, DEVICE, 5.0, 300.0),
+            begin(1, OTHER_DEVICE, 10.0, 500.0),
+            update(1, DEVICE, 150.0, 300.0),
+            update(1, OTHER_DEVICE, 160.0, 500.0),
+            end(1, DEVICE, 300.0, 300.0),
+            end(1, OTHER_DEVICE, 310.0, 500.0),
+        ]);
+
+        let (backend, matched) = run(&config, backend);
+        assert_eq!(matched, vec!["from left to right with two fingers run 'two'"]);
+        assert_eq!(backend.accepted, vec![(1, DEVICE), (1, OTHER_DEVICE)]);
+        assert!(backend.rejected.is_empty());
+    }
+
+    #[test]
+    fn only_actions_of_the_current_layer_match() {
+        let mut config = Config::fake(ACTIONS);
+        config.initial_layer = String::from("locked");
+        let mut backend = ScriptedBackend::new(vec![]);
+        backend.push_swipe(1, DEVICE, (5.0, 400.0), (300.0, 400.0));
+
+        let (_, matched) = run(&config, backend);
+        assert_eq!(matched, vec!["from left to right with one finger run 'locked' in layer 'locked'"]);
+    }
+
+    #[test]
+    fn gesture_without_action_matches_nothing() {
+        let config = Config::fake(ACTIONS);
+        let mut backend = ScriptedBackend::new(vec![]);
+        backend.push_swipe(1, DEVICE, (995.0, 400.0), (700.0, 400.0));
+
+        let (backend, matched) = run(&config, backend);
+        assert!(matched.is_empty());
+        // The touch is part of a gesture, whether or not it has an action.
+        assert_eq!(backend.accepted, vec![(1, DEVICE)]);
+        assert!(backend.rejected.is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+// Splits command strings into words like a POSIX shell does, without
+// performing any expansions.
+
+pub fn split(input: &str) -> Result<Vec<String>, &'static str> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    // Distinguishes an empty quoted word ('') from no word at all.
+    let mut in_word = false;
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => {
+                if in_word {
+                    words.push(word);
+                    word = String::new();
+                    in_word = false;
+                }
+            },
+            '\\' => {
+                in_word = true;
+                match chars.next() {
+                    // Backslash-newline is a line continuation.
+                    Some('\n') => {},
+                    Some(c) => word.push(c),
+                    None => return Err("Command ends with a backslash."),
+                }
+            },
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("Unterminated single quote in command."),
+                    }
+                }
+            },
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            // Within double quotes, backslash only escapes
+                            // characters that are special there.
+                            match chars.next() {
+                                Some('\n') => {},
+                                Some(c) if c == '$' || c == '`' || c == '"' || c == '\\' => word.push(c),
+                                Some(c) => {
+                                    word.push('\\');
+                                    word.push(c);
+                                },
+                                None => return Err("Unterminated double quote in command."),
+                            }
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("Unterminated double quote in command."),
+                    }
+                }
+            },
+            c => {
+                in_word = true;
+                word.push(c);
+            },
+        }
+    }
+
+    if in_word {
+        words.push(word);
+    }
+
+    if words.is_empty() {
+        return Err("Command is empty.");
+    }
+
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(words: &[&str]) -> Result<Vec<String>, &'static str> {
+        Ok(words.iter().map(|&word| String::from(word)).collect())
+    }
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(split("xdotool  key\tsuper\n"), words(&["xdotool", "key", "super"]));
+    }
+
+    #[test]
+    fn quotes_group_words() {
+        assert_eq!(split("notify-send 'Hello world' \"from edgy\""), words(&["notify-send", "Hello world", "from edgy"]));
+        assert_eq!(split("a'b c'\"d\"e"), words(&["ab cde"]));
+        assert_eq!(split("echo '' \"\""), words(&["echo", "", ""]));
+    }
+
+    #[test]
+    fn nested_quotes_are_literal() {
+        assert_eq!(split("echo \"it's\" 'say \"hi\"'"), words(&["echo", "it's", "say \"hi\""]));
+    }
+
+    #[test]
+    fn backslashes() {
+        assert_eq!(split("echo a\\ b \\'c\\\\"), words(&["echo", "a b", "'c\\"]));
+        assert_eq!(split("echo a\\\nb"), words(&["echo", "ab"]));
+        // Single quotes keep backslashes.
+        assert_eq!(split("echo 'a\\nb'"), words(&["echo", "a\\nb"]));
+        // Double quotes only remove them before $, `, " and \\.
+        assert_eq!(split("echo \"\\$HOME \\\" \\\\ \\n\""), words(&["echo", "$HOME \" \\ \\n"]));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(split(""), Err("Command is empty."));
+        assert_eq!(split(" \t"), Err("Command is empty."));
+        assert_eq!(split("echo 'hi"), Err("Unterminated single quote in command."));
+        assert_eq!(split("echo \"hi"), Err("Unterminated double quote in command."));
+        assert_eq!(split("echo \"hi\\"), Err("Unterminated double quote in command."));
+        assert_eq!(split("echo hi\\"), Err("Command ends with a backslash."));
+    }
+}
@@ -0,0 +1,72 @@
+//! SIGINT and SIGTERM stop the event loop instead of killing edgy, so that it
+//! can give back the touchscreens it grabbed or disabled.
+//!
+//! The handler only records the signal and writes to a pipe. The read end of the
+//! pipe is polled by the event loop along with the backend, which wakes it up.
+//! Signal handlers cannot use thread locals, hence the atomics.
+
+use std::io;
+use std::mem::zeroed;
+use std::os::raw::c_int;
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use libc;
+
+static RECEIVED_SIGNAL: AtomicI32 = AtomicI32::new(0);
+static PIPE_WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+static PIPE_READ_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn handle_signal(signal: c_int) {
+    RECEIVED_SIGNAL.store(signal, Ordering::SeqCst);
+    let fd = PIPE_WRITE_FD.load(Ordering::SeqCst);
+    if fd >= 0 {
+        let byte = 0u8;
+        unsafe { libc::write(fd, &byte as *const u8 as *const _, 1) };
+    }
+}
+
+/// Installs the handlers for SIGINT and SIGTERM. Does nothing if they are
+/// already installed.
+pub fn install() -> Result<(), String> {
+    if PIPE_READ_FD.load(Ordering::SeqCst) >= 0 {
+        return Ok(());
+    }
+
+    let mut fds: [c_int; 2] = [-1; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } < 0 {
+        return Err(format!("Could not create a pipe: {}", io::Error::last_os_error()));
+    }
+    PIPE_READ_FD.store(fds[0], Ordering::SeqCst);
+    PIPE_WRITE_FD.store(fds[1], Ordering::SeqCst);
+
+    for &signal in &[libc::SIGINT, libc::SIGTERM] {
+        unsafe {
+            let mut action: libc::sigaction = zeroed();
+            action.sa_sigaction = handle_signal as extern "C" fn(c_int) as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            action.sa_flags = libc::SA_RESTART;
+            if libc::sigaction(signal, &action, null_mut()) < 0 {
+                return Err(format!("Could not handle signal {}: {}", signal,
+                                   io::Error::last_os_error()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The file descriptor that becomes readable when a signal is received.
+pub fn fd() -> Option<c_int> {
+    match PIPE_READ_FD.load(Ordering::SeqCst) {
+        -1 => None,
+        fd => Some(fd),
+    }
+}
+
+/// Returns the signal received, if any.
+pub fn received() -> Option<c_int> {
+    match RECEIVED_SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
@@ -0,0 +1,279 @@
+// Runs commands in the background. Each command gets a thread that reaps it
+// when it exits, logs its exit status and forwards its error output to the log.
+// Commands with a timeout are terminated by a single thread shared by all of
+// them.
+
+use std::cell::RefCell;
+use std::io::{self, BufRead, BufReader};
+use std::mem::zeroed;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc;
+
+// How long a timed out command has to exit after SIGTERM before it is killed.
+const KILL_GRACE_PERIOD_MS: u64 = 2000;
+
+thread_local! {
+    // Sends the commands with a timeout to the thread terminating them,
+    // started with the first one.
+    static TIMEOUTS: RefCell<Option<Sender<Timeout>>> = const { RefCell::new(None) };
+}
+
+struct State {
+    // Signals are only sent until the command is reaped, after which its pid,
+    // and thus its process group id, may be given to another process.
+    reaped: bool,
+    status: Option<ExitStatus>,
+}
+
+#[derive(Clone)]
+pub struct ChildHandle {
+    pub pid: u32,
+    state: Arc<Mutex<State>>,
+}
+
+impl ChildHandle {
+    pub fn has_exited(&self) -> bool {
+        self.state.lock().unwrap().reaped
+    }
+
+    #[cfg(test)]
+    fn exit_status(&self) -> Option<ExitStatus> {
+        self.state.lock().unwrap().status
+    }
+
+    // Sends the signal to the command and the processes it has started.
+    // Returns false if the command has already been reaped.
+    fn signal(&self, signal: libc::c_int) -> bool {
+        let state = self.state.lock().unwrap();
+        if state.reaped {
+            return false;
+        }
+        unsafe { libc::kill(-(self.pid as libc::pid_t), signal); }
+        true
+    }
+
+    // Terminates the command and the processes it has started.
+    pub fn terminate(&self) {
+        self.signal(libc::SIGTERM);
+    }
+}
+
+// A command to terminate at the deadline, and kill if it is still running a
+// grace period later.
+struct Timeout {
+    deadline: Instant,
+    child: ChildHandle,
+    name: String,
+    terminated: bool,
+}
+
+fn enforce_timeouts(receiver: Receiver<Timeout>) {
+    let mut timeouts: Vec<Timeout> = Vec::new();
+    loop {
+        let now = Instant::now();
+        for timeout in timeouts.iter_mut().filter(|timeout| timeout.deadline <= now) {
+            if timeout.child.has_exited() {
+                continue;
+            }
+            if !timeout.terminated {
+                println!("Command {} (pid {}) timed out, terminating it.", timeout.name, timeout.child.pid);
+                timeout.child.signal(libc::SIGTERM);
+                timeout.terminated = true;
+                timeout.deadline = now + Duration::from_millis(KILL_GRACE_PERIOD_MS);
+            }
+            else if timeout.child.signal(libc::SIGKILL) {
+                println!("Command {} (pid {}) did not exit, killed it.", timeout.name, timeout.child.pid);
+            }
+        }
+        timeouts.retain(|timeout| timeout.deadline > now && !timeout.child.has_exited());
+
+        let next_deadline = timeouts.iter().map(|timeout| timeout.deadline).min();
+        let received = match next_deadline {
+            Some(deadline) => receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(timeout) => timeouts.push(timeout),
+            Err(RecvTimeoutError::Timeout) => {},
+            // No more commands are coming, the ones given are still seen to.
+            Err(RecvTimeoutError::Disconnected) => match next_deadline {
+                Some(deadline) => thread::sleep(deadline.saturating_duration_since(Instant::now())),
+                None => return,
+            },
+        }
+    }
+}
+
+fn add_timeout(timeout: Timeout) {
+    TIMEOUTS.with(|sender| {
+        let mut sender = sender.borrow_mut();
+        if sender.is_none() {
+            let (new_sender, receiver) = channel();
+            let spawned = thread::Builder::new()
+                .name(String::from("timeouts"))
+                .spawn(move || enforce_timeouts(receiver));
+            if let Err(error) = spawned {
+                println!("Could not start the command timeout thread: {}", error);
+                return;
+            }
+            *sender = Some(new_sender);
+        }
+
+        let _ = sender.as_ref().unwrap().send(timeout);
+    });
+}
+
+// Waits for the child to exit and reaps it. The exit is waited for without
+// reaping first, so that the pid stays in use while the state is locked.
+fn reap(mut child: Child, name: &str, state: &Mutex<State>) {
+    let pid = child.id();
+    loop {
+        let mut info: libc::siginfo_t = unsafe { zeroed() };
+        if unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, libc::WEXITED | libc::WNOWAIT) } == 0 ||
+            io::Error::last_os_error().kind() != io::ErrorKind::Interrupted
+        {
+            break;
+        }
+    }
+
+    let mut state = state.lock().unwrap();
+    match child.wait() {
+        Ok(status) => {
+            println!("Command {} (pid {}) finished with {}.", name, pid, status);
+            state.status = Some(status);
+        },
+        Err(error) => println!("Could not wait for command {} (pid {}): {}", name, pid, error),
+    }
+    state.reaped = true;
+}
+
+// Starts the command in a new session, so that it is not killed along with
+// edgy. Returns None if the command could not be started.
+pub fn spawn(mut command: Command, name: String, timeout: Option<Duration>) -> Option<ChildHandle> {
+    command.stdin(Stdio::null())
+           .stderr(Stdio::piped());
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(error) => {
+            println!("Failed to run command {}: {}", name, error);
+            return None;
+        },
+    };
+
+    let handle = ChildHandle {
+        pid: child.id(),
+        state: Arc::new(Mutex::new(State { reaped: false, status: None })),
+    };
+
+    if let Some(stderr) = child.stderr.take() {
+        let name = name.clone();
+        let pid = handle.pid;
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines() {
+                match line {
+                    Ok(line) => println!("{} (pid {}): {}", name, pid, line),
+                    Err(_) => break,
+                }
+            }
+        });
+    }
+
+    if let Some(timeout) = timeout {
+        add_timeout(Timeout {
+            deadline: Instant::now() + timeout,
+            child: handle.clone(),
+            name: name.clone(),
+            terminated: false,
+        });
+    }
+
+    let state = handle.state.clone();
+    thread::spawn(move || reap(child, &name, &state));
+
+    Some(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn shell(script: &str, timeout: Option<Duration>) -> ChildHandle {
+        let mut command = Command::new("/bin/sh");
+        command.arg("-c").arg(script);
+        spawn(command, String::from(script), timeout).unwrap()
+    }
+
+    // Returns how long it took for the child to be reaped, None if it was not
+    // within the limit.
+    fn wait_for_exit(child: &ChildHandle, limit: Duration) -> Option<Duration> {
+        let start = Instant::now();
+        while start.elapsed() < limit {
+            if child.has_exited() {
+                return Some(start.elapsed());
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        None
+    }
+
+    #[test]
+    fn exited_command_is_reaped_with_its_status() {
+        let child = shell("exit 3", None);
+        assert!(wait_for_exit(&child, Duration::from_secs(5)).is_some());
+        assert_eq!(child.exit_status().and_then(|status| status.code()), Some(3));
+    }
+
+    #[test]
+    fn reaped_command_is_not_signaled() {
+        let child = shell("true", None);
+        assert!(wait_for_exit(&child, Duration::from_secs(5)).is_some());
+        assert!(!child.signal(libc::SIGTERM));
+    }
+
+    #[test]
+    fn terminate_stops_the_process_group() {
+        let child = shell("sleep 10; exit 0", None);
+        assert!(!child.has_exited());
+        child.terminate();
+        assert!(wait_for_exit(&child, Duration::from_secs(5)).is_some());
+        assert_eq!(child.exit_status().and_then(|status| status.signal()), Some(libc::SIGTERM));
+    }
+
+    #[test]
+    fn timed_out_command_is_terminated() {
+        let child = shell("sleep 10; exit 0", Some(Duration::from_millis(100)));
+        let elapsed = wait_for_exit(&child, Duration::from_secs(5)).unwrap();
+        assert!(elapsed < Duration::from_millis(KILL_GRACE_PERIOD_MS));
+        assert_eq!(child.exit_status().and_then(|status| status.signal()), Some(libc::SIGTERM));
+    }
+
+    #[test]
+    fn command_ignoring_sigterm_is_killed() {
+        let child = shell("trap '' TERM; sleep 10; exit 0", Some(Duration::from_millis(100)));
+        let elapsed = wait_for_exit(&child, Duration::from_secs(5)).unwrap();
+        assert!(elapsed >= Duration::from_millis(KILL_GRACE_PERIOD_MS));
+        assert_eq!(child.exit_status().and_then(|status| status.signal()), Some(libc::SIGKILL));
+    }
+
+    #[test]
+    fn command_exiting_before_its_timeout_is_left_alone() {
+        let child = shell("exit 0", Some(Duration::from_millis(200)));
+        assert!(wait_for_exit(&child, Duration::from_secs(5)).is_some());
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(child.exit_status().and_then(|status| status.code()), Some(0));
+    }
+}
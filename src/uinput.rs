@@ -0,0 +1,110 @@
+// Virtual touchscreens created through /dev/uinput, for passing on the
+// touches of a grabbed touchscreen that are not part of a gesture.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::mem::{size_of, zeroed};
+use std::os::raw::{c_char, c_int, c_ulong};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::slice;
+
+use libc;
+
+use evdev::*;
+
+fn ui_dev_create() -> c_ulong { ioc(IOC_NONE, b'U', 1, 0) }
+fn ui_dev_destroy() -> c_ulong { ioc(IOC_NONE, b'U', 2, 0) }
+fn ui_dev_setup() -> c_ulong { ioc(IOC_WRITE, b'U', 3, size_of::<libc::uinput_setup>()) }
+fn ui_abs_setup() -> c_ulong { ioc(IOC_WRITE, b'U', 4, size_of::<libc::uinput_abs_setup>()) }
+fn ui_set_evbit() -> c_ulong { ioc(IOC_WRITE, b'U', 100, size_of::<c_int>()) }
+fn ui_set_keybit() -> c_ulong { ioc(IOC_WRITE, b'U', 101, size_of::<c_int>()) }
+fn ui_set_absbit() -> c_ulong { ioc(IOC_WRITE, b'U', 103, size_of::<c_int>()) }
+fn ui_set_propbit() -> c_ulong { ioc(IOC_WRITE, b'U', 110, size_of::<c_int>()) }
+
+pub struct VirtualTouchscreen {
+    file: File,
+}
+
+impl VirtualTouchscreen {
+    /// Creates a touchscreen with the name, ids and axes of the device.
+    pub fn clone_device(device: &Device) -> Result<VirtualTouchscreen, String> {
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+            .open("/dev/uinput")
+            .map_err(|e| format!("Could not open /dev/uinput: {}", e))?;
+        let fd = file.as_raw_fd();
+
+        let axes = [ABS_X, ABS_Y, ABS_MT_SLOT, ABS_MT_POSITION_X, ABS_MT_POSITION_Y, ABS_MT_TRACKING_ID];
+
+        let mut setup: libc::uinput_setup = unsafe { zeroed() };
+        setup.id = device.input_id;
+        let name = format!("{} (edgy)", device.name);
+        for (target, &byte) in setup.name.iter_mut().zip(name.as_bytes().iter().take(libc::UINPUT_MAX_NAME_SIZE - 1)) {
+            *target = byte as c_char;
+        }
+
+        let ok = unsafe {
+            libc::ioctl(fd, ui_set_evbit() as libc::Ioctl, EV_SYN as c_int) >= 0 &&
+            libc::ioctl(fd, ui_set_evbit() as libc::Ioctl, EV_KEY as c_int) >= 0 &&
+            libc::ioctl(fd, ui_set_evbit() as libc::Ioctl, EV_ABS as c_int) >= 0 &&
+            libc::ioctl(fd, ui_set_keybit() as libc::Ioctl, BTN_TOUCH as c_int) >= 0 &&
+            libc::ioctl(fd, ui_set_propbit() as libc::Ioctl, INPUT_PROP_DIRECT as c_int) >= 0 &&
+            axes.iter().all(|&axis| {
+                let mut abs_setup: libc::uinput_abs_setup = zeroed();
+                abs_setup.code = axis;
+                abs_setup.absinfo = match axis {
+                    // Single touch axes follow the touch in the first used
+                    // slot, with the range of the multi-touch axes.
+                    ABS_X => device.axis_info(ABS_MT_POSITION_X),
+                    ABS_Y => device.axis_info(ABS_MT_POSITION_Y),
+                    _ => device.axis_info(axis),
+                }.unwrap_or_else(|| zeroed());
+                if axis == ABS_MT_TRACKING_ID {
+                    abs_setup.absinfo.minimum = 0;
+                    abs_setup.absinfo.maximum = i32::MAX;
+                }
+                libc::ioctl(fd, ui_set_absbit() as libc::Ioctl, axis as c_int) >= 0 &&
+                libc::ioctl(fd, ui_abs_setup() as libc::Ioctl, &abs_setup) >= 0
+            }) &&
+            libc::ioctl(fd, ui_dev_setup() as libc::Ioctl, &setup) >= 0 &&
+            libc::ioctl(fd, ui_dev_create() as libc::Ioctl) >= 0
+        };
+        if !ok {
+            return Err(format!("Could not create a virtual touchscreen: {}", io::Error::last_os_error()));
+        }
+
+        println!("Passing touches of {} through {}.", device.name, name);
+        Ok(VirtualTouchscreen { file: file })
+    }
+
+    /// Writes the events, as (type, code, value), followed by a report.
+    pub fn send(&mut self, events: &[(u16, u16, i32)]) -> io::Result<()> {
+        let mut buffer = Vec::new();
+        for &(event_type, code, value) in events.iter().chain(&[(EV_SYN, SYN_REPORT, 0)]) {
+            let mut event: libc::input_event = unsafe { zeroed() };
+            event.type_ = event_type;
+            event.code = code;
+            event.value = value;
+            buffer.extend_from_slice(unsafe {
+                slice::from_raw_parts(&event as *const _ as *const u8, size_of::<libc::input_event>())
+            });
+        }
+        self.file.write_all(&buffer)
+    }
+}
+
+#[cfg(test)]
+impl VirtualTouchscreen {
+    /// Writes the events to the file instead of a uinput device.
+    pub fn from_file(file: File) -> VirtualTouchscreen {
+        VirtualTouchscreen { file: file }
+    }
+}
+
+impl Drop for VirtualTouchscreen {
+    fn drop(&mut self) {
+        unsafe { libc::ioctl(self.file.as_raw_fd(), ui_dev_destroy() as libc::Ioctl) };
+    }
+}
@@ -1,19 +1,439 @@
-use x11::xlib;
-use std::ptr::{null_mut, null};
+//! The connection to the X server. Xlib calls elsewhere take the display and
+//! root window from an XConnection, which is passed around in the Config.
 
-pub static mut display:Option<*mut xlib::Display> = None;
-pub static mut root_window:xlib::Window = 0;
+use x11::{xlib, xrandr, xinput2};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::iter;
+use std::os::raw::{c_int, c_uchar};
+use std::ptr::{null, null_mut};
+use std::time::Duration;
 
-pub fn initialize_x11() {
-    unsafe { 
-        let d = xlib::XOpenDisplay(null());
+use backend::poll;
+use device_spec::DeviceInfo;
 
-        if d == null_mut() {
-            panic!("Display could not be opened.");
+// The devices whose touch input was disabled through the connection, given
+// back when it is dropped.
+#[derive(Default)]
+struct DisabledDevices {
+    // Devices grabbed for disabling touch input.
+    grabbed: Vec<i32>,
+    // Devices disabled with the "Device Enabled" property.
+    disabled: Vec<i32>,
+    // Names of devices removed while grabbed or disabled. A device that is
+    // plugged back in gets a new id, so it is recognized by its name.
+    removed: Vec<String>,
+}
+
+fn set_listed(device_ids: &mut Vec<i32>, device_id: i32, listed: bool) {
+    device_ids.retain(|&id| id != device_id);
+    if listed {
+        device_ids.push(device_id);
+    }
+}
+
+impl DisabledDevices {
+    fn device_removed(&mut self, device_id: i32, name: &str) {
+        if self.grabbed.contains(&device_id) || self.disabled.contains(&device_id) {
+            self.removed.push(String::from(name));
+        }
+        set_listed(&mut self.grabbed, device_id, false);
+        set_listed(&mut self.disabled, device_id, false);
+    }
+
+    fn take_removed(&mut self, name: &str) -> bool {
+        match self.removed.iter().position(|removed_name| removed_name == name) {
+            Some(index) => {
+                self.removed.remove(index);
+                true
+            },
+            None => false,
         }
+    }
+
+    // Returns the grabbed and the disabled devices, forgetting all of them.
+    fn take_all(&mut self) -> (Vec<i32>, Vec<i32>) {
+        self.removed.clear();
+        (self.grabbed.split_off(0), self.disabled.split_off(0))
+    }
+}
+
+/// An Xlib display connection, closed when dropped. Devices grabbed or
+/// disabled through it are then ungrabbed and enabled again.
+pub struct XConnection {
+    display: *mut xlib::Display,
+    root_window: xlib::Window,
+    disabled_devices: RefCell<DisabledDevices>,
+    // Core events read by the touch backend, for the overlay.
+    deferred_events: RefCell<Vec<xlib::XEvent>>,
+}
+
+/// An XRandR output, i.e. a monitor, and the part of the root window it
+/// shows.
+pub struct Output {
+    /// E.g. "eDP-1".
+    pub name: String,
+    /// Left edge on the root window.
+    pub x: i32,
+    /// Top edge on the root window.
+    pub y: i32,
+    /// Width in pixels.
+    pub width: u32,
+    /// Height in pixels.
+    pub height: u32,
+}
+
+impl Output {
+    /// Returns whether the point on the root window is on the output.
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.x as f64 && x < self.x as f64 + self.width as f64 &&
+        y >= self.y as f64 && y < self.y as f64 + self.height as f64
+    }
+}
+
+impl XConnection {
+    /// Connects to the X server given by $DISPLAY.
+    pub fn open() -> Result<XConnection, String> {
+        unsafe {
+            let display = xlib::XOpenDisplay(null());
+
+            if display.is_null() {
+                return Err(String::from("Display could not be opened."));
+            }
+
+            Ok(XConnection {
+                display: display,
+                root_window: xlib::XRootWindow(display, xlib::XDefaultScreen(display)),
+                disabled_devices: RefCell::new(DisabledDevices::default()),
+                deferred_events: RefCell::new(Vec::new()),
+            })
+        }
+    }
+
+    /// The Xlib display, for Xlib calls.
+    pub fn display(&self) -> *mut xlib::Display {
+        self.display
+    }
+
+    /// The root window of the default screen.
+    pub fn root_window(&self) -> xlib::Window {
+        self.root_window
+    }
+
+    /// Returns whether the device was grabbed for disabling touch input.
+    pub fn is_grabbed(&self, device_id: i32) -> bool {
+        self.disabled_devices.borrow().grabbed.contains(&device_id)
+    }
+
+    /// Records whether the device is grabbed, for ungrabbing it on drop.
+    pub fn set_grabbed(&self, device_id: i32, grabbed: bool) {
+        set_listed(&mut self.disabled_devices.borrow_mut().grabbed, device_id, grabbed);
+    }
+
+    /// The atom of the "Device Enabled" XInput device property.
+    pub fn device_enabled_atom(&self) -> xlib::Atom {
+        let name = CString::new("Device Enabled").unwrap();
+        unsafe { xlib::XInternAtom(self.display, name.as_ptr(), xlib::False) }
+    }
+
+    /// Sets the "Device Enabled" property of the XInput device. A device
+    /// disabled with it is enabled again on drop.
+    pub fn set_device_enabled(&self, device_id: i32, enabled: bool) {
+        let mut value: c_uchar = enabled as c_uchar;
+        unsafe {
+            xinput2::XIChangeProperty(self.display,
+                                      device_id,
+                                      self.device_enabled_atom(),
+                                      xlib::XA_INTEGER,
+                                      8,
+                                      xlib::PropModeReplace,
+                                      &mut value,
+                                      1);
+            xlib::XSync(self.display, xlib::False);
+        }
+        set_listed(&mut self.disabled_devices.borrow_mut().disabled, device_id, !enabled);
+    }
+
+    /// Forgets the grab or disabling of a device that was removed. If it was
+    /// grabbed or disabled, its name is kept for take_removed_disabled().
+    pub fn device_removed(&self, device_id: i32, name: &str) {
+        self.disabled_devices.borrow_mut().device_removed(device_id, name);
+    }
+
+    /// Returns whether a device with the name was removed while grabbed or
+    /// disabled, i.e. should be disabled when plugged back in. Each removed
+    /// device is only returned once.
+    pub fn take_removed_disabled(&self, name: &str) -> bool {
+        self.disabled_devices.borrow_mut().take_removed(name)
+    }
+
+    /// Keeps an event that is not for the reader of the events, e.g. an Expose
+    /// event of a window, for take_deferred_events().
+    pub fn defer_event(&self, event: xlib::XEvent) {
+        self.deferred_events.borrow_mut().push(event);
+    }
+
+    /// Returns the events given to defer_event() since the last call.
+    pub fn take_deferred_events(&self) -> Vec<xlib::XEvent> {
+        self.deferred_events.borrow_mut().split_off(0)
+    }
+
+    /// Waits until an X event is available, one of the other file descriptors is
+    /// readable, or the timeout expires. Returns whether an X event is available.
+    pub fn wait_for_event(&self, timeout: Option<Duration>, other_fds: &[c_int]) -> bool {
+        unsafe {
+            if xlib::XPending(self.display) > 0 {
+                return true;
+            }
+
+            let x_fd = xlib::XConnectionNumber(self.display);
+            let fds: Vec<c_int> = iter::once(x_fd).chain(other_fds.iter().cloned()).collect();
+            poll(&fds, timeout)[0] && xlib::XPending(self.display) > 0
+        }
+    }
+
+    /// Returns the active XRandR outputs, i.e. monitors, ordered from left to right.
+    pub fn get_outputs(&self) -> Vec<Output> {
+        let mut result = Vec::new();
+
+        unsafe {
+            let resources = xrandr::XRRGetScreenResourcesCurrent(self.display, self.root_window);
+            if resources.is_null() {
+                return result;
+            }
+
+            for i in 0..(*resources).noutput {
+                let output_info = xrandr::XRRGetOutputInfo(self.display,
+                                                           resources,
+                                                           *(*resources).outputs.offset(i as isize));
+                if output_info.is_null() {
+                    continue;
+                }
+
+                if (*output_info).crtc != 0 {
+                    let crtc_info = xrandr::XRRGetCrtcInfo(self.display, resources, (*output_info).crtc);
+                    if !crtc_info.is_null() {
+                        result.push(Output {
+                            name: CStr::from_ptr((*output_info).name).to_string_lossy().into_owned(),
+                            x: (*crtc_info).x,
+                            y: (*crtc_info).y,
+                            width: (*crtc_info).width,
+                            height: (*crtc_info).height,
+                        });
+                        xrandr::XRRFreeCrtcInfo(crtc_info);
+                    }
+                }
+
+                xrandr::XRRFreeOutputInfo(output_info);
+            }
+
+            xrandr::XRRFreeScreenResources(resources);
+        }
+
+        result.sort_by_key(|output| (output.x, output.y));
+        result
+    }
+
+    /// Returns the name of the XRandR output (e.g. "eDP-1") that contains the
+    /// given point on the root window.
+    pub fn get_output_name_at(&self, x: f64, y: f64) -> Option<String> {
+        self.get_outputs().into_iter()
+            .find(|output| output.contains(x, y))
+            .map(|output| output.name)
+    }
+
+    /// Returns whether the X server has the XInput extension.
+    pub fn has_xinput(&self) -> bool {
+        let mut opcode:c_int = 0;
+        let mut event:c_int = 0;
+        let mut error:c_int = 0;
+
+        let xinput_name = CString::new("XInputException").unwrap();
+
+        unsafe{
+            xlib::XQueryExtension(self.display, xinput_name.as_ptr(), &mut opcode, &mut event, &mut error) != xlib::True
+        }
+    }
+
+    /// Returns whether the X server supports XInput 2.2, which added touch
+    /// events.
+    pub fn has_xinput_2_2(&self) -> bool {
+        let mut version_major = 2;
+        let mut version_minor = 2;
+
+        unsafe {
+            if xinput2::XIQueryVersion(self.display, &mut version_major, &mut version_minor) != xlib::Success as c_int 
+            {
+                return false;
+            }
+        }
+
+        version_major > 2 || version_major == 2 && version_minor >= 2
+    }
+
+    /// Returns the size of the root window, i.e. of all screens together.
+    pub fn get_root_window_size(&self) -> Result<(u32, u32), String> {
+        let mut root:xlib::Window = 0;
+        let mut x = 0;
+        let mut y = 0;
+        let mut width = 0;
+        let mut height = 0;
+        let mut border_width = 0;
+        let mut depth = 0;
+        if unsafe { xlib::XGetGeometry(self.display,
+                                       self.root_window,
+                                       &mut root,
+                                       &mut x,
+                                       &mut y,
+                                       &mut width,
+                                       &mut height,
+                                       &mut border_width, &mut depth) } == xlib::False
+        {
+            return Err(String::from("Could not get window geometry."));
+        }
+
+        Ok((width, height))
+    }
+
+    /// Calls f with each XInput device, master and slave. The device info is
+    /// only valid during the call.
+    pub fn for_each_xinput_device<F>(&self, f: &mut F) -> ()
+        where F: FnMut(*mut xinput2::XIDeviceInfo) -> () 
+    {
+        let mut device_count = 0;
+        unsafe {
+            let devices = xinput2::XIQueryDevice(self.display, xinput2::XIAllDevices, &mut device_count);
+            for i in 0..device_count {
+                let device = devices.offset(i as isize);
+                f(device);
+            }
+            xinput2::XIFreeDeviceInfo(devices);
+        }
+    }
+
+    /// Returns the items of a format 32 property of the XInput device, e.g.
+    /// "Device Product ID", or None if the device does not have it.
+    pub fn get_xinput_property(&self, device_id: c_int, name: &str) -> Option<Vec<u32>> {
+        let name = CString::new(name).unwrap();
+        let mut actual_type = 0;
+        let mut actual_format = 0;
+        let mut num_items = 0;
+        let mut bytes_after = 0;
+        let mut data: *mut c_uchar = null_mut();
+
+        unsafe {
+            let atom = xlib::XInternAtom(self.display, name.as_ptr(), xlib::True);
+            if atom == 0 ||
+               xinput2::XIGetProperty(self.display, device_id, atom, 0, 64, xlib::False,
+                                      xlib::AnyPropertyType as xlib::Atom,
+                                      &mut actual_type, &mut actual_format, &mut num_items,
+                                      &mut bytes_after, &mut data) != xlib::Success as c_int ||
+               data.is_null()
+            {
+                return None;
+            }
+
+            // Format 32 items are returned as 32 bit values.
+            let result = if actual_format == 32 {
+                Some((0..num_items as usize).map(|i| *(data as *const u32).add(i)).collect())
+            }
+            else {
+                None
+            };
+            xlib::XFree(data as *mut _);
+            result
+        }
+    }
+
+    /// Returns the physical XInput devices, i.e. not the master pointers and
+    /// keyboards.
+    pub fn list_xinput_devices(&self) -> Vec<DeviceInfo> {
+        let mut result = Vec::new();
+
+        self.for_each_xinput_device(&mut |device: *mut xinput2::XIDeviceInfo| {
+            unsafe {
+                let device = &*device;
+                if device._use == xinput2::XIMasterPointer || device._use == xinput2::XIMasterKeyboard {
+                    return;
+                }
+
+                let is_touchscreen = (0..device.num_classes as isize)
+                    .map(|i| *device.classes.offset(i))
+                    .any(|class| {
+                        (*class)._type == xinput2::XITouchClass &&
+                        (*(class as *mut xinput2::XITouchClassInfo)).mode == xinput2::XIDirectTouch
+                    });
+
+                result.push(DeviceInfo {
+                    id: device.deviceid,
+                    name: CStr::from_ptr(device.name).to_string_lossy().into_owned(),
+                    vendor_product: None,
+                    is_touchscreen: is_touchscreen,
+                });
+            }
+        });
+
+        for device in &mut result {
+            device.vendor_product = match self.get_xinput_property(device.id, "Device Product ID") {
+                Some(ref ids) if ids.len() == 2 => Some((ids[0] as u16, ids[1] as u16)),
+                _ => None,
+            };
+        }
+
+        result
+    }
+}
+
+impl Drop for XConnection {
+    fn drop(&mut self) {
+        // The property outlives the connection, unlike grabs, so without this
+        // a device would stay disabled after e.g. a panic.
+        let (grabbed, disabled) = self.disabled_devices.borrow_mut().take_all();
+        for device_id in disabled {
+            println!("Enabling device #{}.", device_id);
+            self.set_device_enabled(device_id, true);
+        }
+        unsafe {
+            for device_id in grabbed {
+                println!("Ungrabbing device #{}.", device_id);
+                xinput2::XIUngrabDevice(self.display, device_id, xlib::CurrentTime);
+            }
+            xlib::XCloseDisplay(self.display);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled_devices_are_all_given_back() {
+        let mut devices = DisabledDevices::default();
+        set_listed(&mut devices.grabbed, 11, true);
+        set_listed(&mut devices.grabbed, 12, true);
+        set_listed(&mut devices.grabbed, 12, false);
+        set_listed(&mut devices.disabled, 13, true);
+        set_listed(&mut devices.disabled, 13, true);
+        set_listed(&mut devices.disabled, 14, true);
+
+        assert_eq!(devices.take_all(), (vec![11], vec![13, 14]));
+        assert_eq!(devices.take_all(), (vec![], vec![]));
+    }
 
-        display = Some(d);
+    #[test]
+    fn removed_devices_are_not_given_back_but_remembered() {
+        let mut devices = DisabledDevices::default();
+        set_listed(&mut devices.grabbed, 11, true);
+        set_listed(&mut devices.disabled, 12, true);
+        devices.device_removed(11, "ELAN Touchscreen");
+        devices.device_removed(12, "Wacom Touch");
+        devices.device_removed(13, "Never Disabled");
 
-        root_window = xlib::XRootWindow(display.unwrap(), xlib::XDefaultScreen(d));
-    };
+        assert!(devices.take_removed("Wacom Touch"));
+        assert!(!devices.take_removed("Wacom Touch"));
+        assert!(!devices.take_removed("Never Disabled"));
+        assert_eq!(devices.take_all(), (vec![], vec![]));
+        assert!(!devices.take_removed("ELAN Touchscreen"));
+    }
 }
@@ -0,0 +1,440 @@
+//! Touches from an XInput 2.2 touch grab on the root window.
+//!
+//! Touchscreens are direct touch devices, their touches are at points on the
+//! screen. Touchpads are dependent touch devices, their touches are reported at
+//! the pointer. For edge swipes on the touchpad's own surface, the position on
+//! the touchpad is read from the touch's valuators instead and scaled to the
+//! screen, as if the touchpad covered the whole screen. A touchpad touch is
+//! held back until both of its axes are known.
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::mem::{zeroed, transmute};
+use std::os::raw::{c_int, c_uchar};
+use std::rc::Rc;
+use std::slice;
+use std::time::Duration;
+use x11::{xlib, xinput2};
+
+use backend::{InputBackend, TouchEvent};
+use xconn::XConnection;
+
+#[derive(Copy, Clone)]
+struct Valuator {
+    number: c_int,
+    min: f64,
+    max: f64,
+}
+
+impl Valuator {
+    fn scale(&self, value: f64, size: f64) -> f64 {
+        if self.max <= self.min {
+            return 0.0;
+        }
+        (value - self.min) / (self.max - self.min) * (size - 1.0)
+    }
+}
+
+// The axes of a touchpad's surface.
+#[derive(Copy, Clone)]
+struct TouchpadAxes {
+    x: Valuator,
+    y: Valuator,
+}
+
+// What an event of a touchpad touch is passed on as.
+#[derive(PartialEq, Debug)]
+enum TouchpadUpdate {
+    // Not passed on, an axis of the touch is still unknown.
+    Held,
+    Begin(f64, f64),
+    Update(f64, f64),
+    End(f64, f64),
+    // Ended before both axes were known, so it was never passed on.
+    Dropped,
+}
+
+// A touchpad touch. The valuators of an event only include the axes that
+// changed, so the last position is kept.
+#[derive(Default)]
+struct TouchpadTouch {
+    x: Option<f64>,
+    y: Option<f64>,
+    // Whether the touch has been passed on with TouchpadUpdate::Begin.
+    has_begun: bool,
+}
+
+impl TouchpadTouch {
+    // Takes the axes included in an event, None for the others.
+    fn update(&mut self, x: Option<f64>, y: Option<f64>, ends: bool) -> TouchpadUpdate {
+        self.x = x.or(self.x);
+        self.y = y.or(self.y);
+        let has_begun = self.has_begun;
+        match (self.x, self.y) {
+            _ if ends && !has_begun => TouchpadUpdate::Dropped,
+            (Some(x), Some(y)) => {
+                self.has_begun = true;
+                if ends {
+                    TouchpadUpdate::End(x, y)
+                }
+                else if has_begun {
+                    TouchpadUpdate::Update(x, y)
+                }
+                else {
+                    TouchpadUpdate::Begin(x, y)
+                }
+            },
+            _ => TouchpadUpdate::Held,
+        }
+    }
+}
+
+/// Reads the touches of an XInput 2.2 touch grab on the root window.
+pub struct XInputBackend {
+    x11: Rc<XConnection>,
+    screen_width: f64,
+    screen_height: f64,
+    // Whether an XI_HierarchyChanged event has been received since the last
+    // devices_changed().
+    devices_changed: bool,
+    // Axes of the source devices touches have come from, None for devices that
+    // are not touchpads. Forgotten when devices change, as ids are reused.
+    touchpads: HashMap<i32, Option<TouchpadAxes>>,
+    // Touchpad touches by source device and touch id, as touch ids are only
+    // unique per device.
+    touchpad_touches: HashMap<(i32, i32), TouchpadTouch>,
+}
+
+fn intern_atom(x11: &XConnection, name: &str) -> xlib::Atom {
+    let name = CString::new(name).unwrap();
+    unsafe { xlib::XInternAtom(x11.display(), name.as_ptr(), xlib::True) }
+}
+
+// Returns the axes of the device if it is a touchpad, i.e. a dependent touch
+// device.
+fn query_touchpad_axes(x11: &XConnection, device_id: i32) -> Option<TouchpadAxes> {
+    let x_label = intern_atom(x11, "Abs MT Position X");
+    let y_label = intern_atom(x11, "Abs MT Position Y");
+
+    let mut is_dependent = false;
+    let mut valuators = Vec::new();
+    unsafe {
+        let mut device_count = 0;
+        let devices = xinput2::XIQueryDevice(x11.display(), device_id, &mut device_count);
+        if devices.is_null() {
+            return None;
+        }
+        if device_count > 0 {
+            let device = &*devices;
+            for i in 0..device.num_classes as usize {
+                let class = *device.classes.add(i);
+                match (*class)._type {
+                    xinput2::XITouchClass => {
+                        is_dependent = (*(class as *const xinput2::XITouchClassInfo)).mode == xinput2::XIDependentTouch;
+                    },
+                    xinput2::XIValuatorClass => {
+                        let valuator = &*(class as *const xinput2::XIValuatorClassInfo);
+                        valuators.push((valuator.label, Valuator { number: valuator.number, min: valuator.min, max: valuator.max }));
+                    },
+                    _ => {},
+                }
+            }
+        }
+        xinput2::XIFreeDeviceInfo(devices);
+    }
+
+    if !is_dependent {
+        return None;
+    }
+
+    // The first two valuators of a touch device are its position, unless the
+    // position axes are labeled.
+    let find = |label: xlib::Atom, number: c_int| {
+        valuators.iter().find(|&&(valuator_label, _)| label != 0 && valuator_label == label)
+            .or_else(|| valuators.iter().find(|&&(_, valuator)| valuator.number == number))
+            .map(|&(_, valuator)| valuator)
+    };
+    match (find(x_label, 0), find(y_label, 1)) {
+        (Some(x), Some(y)) => Some(TouchpadAxes { x: x, y: y }),
+        _ => None,
+    }
+}
+
+// Returns the value of the valuator if the event includes it.
+fn valuator_value(valuators: &xinput2::XIValuatorState, number: c_int) -> Option<f64> {
+    let mask = unsafe { slice::from_raw_parts(valuators.mask, valuators.mask_len as usize) };
+    if number < 0 || number as usize >= mask.len() * 8 || !xinput2::XIMaskIsSet(mask, number) {
+        return None;
+    }
+    // Values are only given for the valuators in the mask.
+    let index = (0..number).filter(|&i| xinput2::XIMaskIsSet(mask, i)).count();
+    Some(unsafe { *valuators.values.add(index) })
+}
+
+impl XInputBackend {
+    /// Fails if the X server does not support XInput 2.2.
+    pub fn new(x11: Rc<XConnection>) -> Result<XInputBackend, String> {
+        if !x11.has_xinput() {
+            return Err(String::from("XInput extension is not available."));
+        }
+
+        if !x11.has_xinput_2_2() {
+            return Err(String::from("XInput extension is below XInput 2.2."));
+        }
+
+        let (width, height) = x11.get_root_window_size()?;
+        Ok(XInputBackend {
+            x11: x11,
+            screen_width: width as f64,
+            screen_height: height as f64,
+            devices_changed: false,
+            touchpads: HashMap::new(),
+            touchpad_touches: HashMap::new(),
+        })
+    }
+
+    fn touchpad_axes(&mut self, device_id: i32) -> Option<TouchpadAxes> {
+        let x11 = &self.x11;
+        *self.touchpads.entry(device_id).or_insert_with(|| {
+            let axes = query_touchpad_axes(x11, device_id);
+            if axes.is_some() {
+                println!("Device #{} is a touchpad, edges are those of its surface.", device_id);
+            }
+            axes
+        })
+    }
+
+    // Returns the event type and position of the touch, on the screen for
+    // touchscreens and scaled from the surface for touchpads. None while a
+    // touchpad touch is held back.
+    fn touch_position(&mut self, event_data: &xinput2::XIDeviceEvent) -> Option<(c_int, f64, f64)> {
+        let axes = match self.touchpad_axes(event_data.sourceid) {
+            Some(axes) => axes,
+            None => return Some((event_data.evtype, event_data.root_x, event_data.root_y)),
+        };
+
+        let key = (event_data.sourceid, event_data.detail);
+        let x = valuator_value(&event_data.valuators, axes.x.number)
+            .map(|value| axes.x.scale(value, self.screen_width));
+        let y = valuator_value(&event_data.valuators, axes.y.number)
+            .map(|value| axes.y.scale(value, self.screen_height));
+        let ends = event_data.evtype == xinput2::XI_TouchEnd;
+        let update = self.touchpad_touches.entry(key).or_default().update(x, y, ends);
+        if ends {
+            self.touchpad_touches.remove(&key);
+        }
+
+        match update {
+            TouchpadUpdate::Held => None,
+            TouchpadUpdate::Begin(x, y) => Some((xinput2::XI_TouchBegin, x, y)),
+            TouchpadUpdate::Update(x, y) => Some((xinput2::XI_TouchUpdate, x, y)),
+            TouchpadUpdate::End(x, y) => Some((xinput2::XI_TouchEnd, x, y)),
+            TouchpadUpdate::Dropped => {
+                // Nothing else will decide on it.
+                self.allow_touch(event_data.detail, event_data.deviceid, xinput2::XIRejectTouch);
+                None
+            },
+        }
+    }
+
+    fn allow_touch(&self, touch_id: i32, device_id: i32, event_mode: c_int) {
+        if device_id != 2 {
+            // The event is from device grab, not touch grab, ignore it.
+            return;
+        }
+
+        unsafe {
+            xinput2::XIAllowTouchEvents(self.x11.display(),
+                                        device_id,
+                                        touch_id as u32,
+                                        self.x11.root_window(),
+                                        event_mode);
+        }
+    }
+}
+
+impl InputBackend for XInputBackend {
+    fn grab(&mut self) -> Result<(), String> {
+        // The pointer root device (id=2) is grabbed here. Grabbing a single device
+        // does not seem to work (events are received AFTER they are processed by
+        // other applications, even for grabs.)
+
+        let mut mask: [c_uchar; 4] = [0; 4];
+        let mut modifiers =  xinput2::XIGrabModifiers {
+            modifiers: 1 << 31,
+            status: 0,
+        };
+        let mut input_event_mask = xinput2::XIEventMask {
+            deviceid: 2, //xinput2::XIAllDevices,
+            mask_len: mask.len() as i32,
+            mask: mask.as_mut_ptr(),
+        };
+
+        for &event in &[xinput2::XI_TouchBegin,
+                        xinput2::XI_TouchUpdate,
+                        xinput2::XI_TouchEnd,
+                        xinput2::XI_TouchOwnership, ]
+        {
+            xinput2::XISetMask(&mut mask, event);
+        }
+
+        if unsafe { xinput2::XIGrabTouchBegin(self.x11.display(),
+                                              2,
+                                              self.x11.root_window(),
+                                              /*owner_events=*/xlib::False,
+                                              &mut input_event_mask,
+                                              /*num_modifiers=*/1,
+                                              &mut modifiers) } != 0
+        {
+            return Err(String::from("Could not grab TouchBegin."));
+        }
+
+        // Devices being added and removed, e.g. a touchscreen being plugged in.
+        let mut hierarchy_mask: [c_uchar; 4] = [0; 4];
+        xinput2::XISetMask(&mut hierarchy_mask, xinput2::XI_HierarchyChanged);
+        let mut hierarchy_event_mask = xinput2::XIEventMask {
+            deviceid: xinput2::XIAllDevices,
+            mask_len: hierarchy_mask.len() as i32,
+            mask: hierarchy_mask.as_mut_ptr(),
+        };
+        unsafe { xinput2::XISelectEvents(self.x11.display(), self.x11.root_window(), &mut hierarchy_event_mask, 1) };
+        Ok(())
+    }
+
+    fn ungrab(&mut self) {
+        let mut modifiers = xinput2::XIGrabModifiers {
+            modifiers: 1 << 31,
+            status: 0,
+        };
+        unsafe {
+            xinput2::XIUngrabTouchBegin(self.x11.display(),
+                                        2,
+                                        self.x11.root_window(),
+                                        /*num_modifiers=*/1,
+                                        &mut modifiers);
+            xlib::XSync(self.x11.display(), xlib::False);
+        }
+    }
+
+    fn wait(&mut self, timeout: Option<Duration>, other_fds: &[c_int]) -> bool {
+        self.x11.wait_for_event(timeout, other_fds)
+    }
+
+    fn next_event(&mut self) -> Option<TouchEvent> {
+        let mut event: xlib::XEvent = unsafe { zeroed() };
+
+        unsafe {
+            if xlib::XPending(self.x11.display()) == 0 {
+                return None;
+            }
+            xlib::XNextEvent(self.x11.display(), &mut event);
+        }
+        // Of the core events, only the overlay's Expose events are of use.
+        if event.get_type() != xlib::GenericEvent {
+            if event.get_type() == xlib::Expose {
+                self.x11.defer_event(event);
+            }
+            return None;
+        }
+
+        let mut cookie:xlib::XGenericEventCookie = From::from(event);
+
+        if unsafe { xlib::XGetEventData(self.x11.display(), &mut cookie) } != xlib::True {
+            println!("XGetEventData failed.");
+            return None;
+        }
+
+        let result = match cookie.evtype {
+            xinput2::XI_TouchBegin | xinput2::XI_TouchUpdate | xinput2::XI_TouchEnd => {
+                let event_data: &xinput2::XIDeviceEvent = unsafe{ transmute(cookie.data) };
+                let touch_id = event_data.detail;
+                let source_id = event_data.sourceid;
+                self.touch_position(event_data).map(|(evtype, x, y)| match evtype {
+                    // The root pointer device is grabbed since grabbing a
+                    // single device does not work, thus the physical device is
+                    // in sourceid, not deviceid.
+                    xinput2::XI_TouchBegin => TouchEvent::Begin {
+                        touch_id: touch_id,
+                        device_id: event_data.deviceid,
+                        source_id: source_id,
+                        x: x,
+                        y: y,
+                    },
+                    xinput2::XI_TouchUpdate => TouchEvent::Update { touch_id: touch_id, source_id: source_id, x: x, y: y },
+                    _ => TouchEvent::End { touch_id: touch_id, source_id: source_id, x: x, y: y },
+                })
+            },
+            xinput2::XI_HierarchyChanged => {
+                let event_data: &xinput2::XIHierarchyEvent = unsafe { &*(cookie.data as *const _) };
+                if event_data.flags & (xinput2::XISlaveAdded | xinput2::XISlaveRemoved |
+                                       xinput2::XIDeviceEnabled | xinput2::XIDeviceDisabled) != 0
+                {
+                    self.devices_changed = true;
+                    self.touchpads.clear();
+                }
+                // The touches of a removed device will not end, and its id may
+                // be given to another device.
+                let infos = match event_data.num_info {
+                    0 => &[][..],
+                    count => unsafe { slice::from_raw_parts(event_data.info, count as usize) },
+                };
+                for info in infos.iter().filter(|info| info.flags & (xinput2::XISlaveRemoved | xinput2::XIDeviceDisabled) != 0) {
+                    self.touchpad_touches.retain(|&(source_id, _), _| source_id != info.deviceid);
+                }
+                None
+            },
+            _ => None,
+        };
+
+        unsafe { xlib::XFreeEventData(self.x11.display(), &mut cookie) };
+        result
+    }
+
+    fn reject_touch(&mut self, touch_id: i32, device_id: i32) {
+        self.allow_touch(touch_id, device_id, xinput2::XIRejectTouch);
+    }
+
+    fn accept_touch(&mut self, touch_id: i32, device_id: i32) {
+        self.allow_touch(touch_id, device_id, xinput2::XIAcceptTouch);
+    }
+
+    fn devices_changed(&mut self) -> bool {
+        let devices_changed = self.devices_changed;
+        self.devices_changed = false;
+        devices_changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn touchpad_touch_begins_once_both_axes_are_known() {
+        let mut touch = TouchpadTouch::default();
+        assert_eq!(touch.update(Some(10.0), None, false), TouchpadUpdate::Held);
+        assert_eq!(touch.update(None, None, false), TouchpadUpdate::Held);
+        assert_eq!(touch.update(None, Some(20.0), false), TouchpadUpdate::Begin(10.0, 20.0));
+        assert_eq!(touch.update(Some(30.0), None, false), TouchpadUpdate::Update(30.0, 20.0));
+        assert_eq!(touch.update(None, None, true), TouchpadUpdate::End(30.0, 20.0));
+    }
+
+    #[test]
+    fn touchpad_touch_with_both_axes_begins_at_once() {
+        let mut touch = TouchpadTouch::default();
+        assert_eq!(touch.update(Some(0.0), Some(5.0), false), TouchpadUpdate::Begin(0.0, 5.0));
+    }
+
+    #[test]
+    fn touchpad_touch_ending_before_it_began_is_dropped() {
+        let mut touch = TouchpadTouch::default();
+        assert_eq!(touch.update(Some(10.0), None, false), TouchpadUpdate::Held);
+        assert_eq!(touch.update(None, None, true), TouchpadUpdate::Dropped);
+
+        // Also when the last axis only comes with the end, as there would be
+        // no event left to end it after beginning it.
+        let mut touch = TouchpadTouch::default();
+        assert_eq!(touch.update(Some(10.0), None, false), TouchpadUpdate::Held);
+        assert_eq!(touch.update(None, Some(20.0), true), TouchpadUpdate::Dropped);
+    }
+}